@@ -1,36 +1,220 @@
 #import bevy_core_pipeline::fullscreen_vertex_shader
 #import "shaders/utils.wgsl"
 
+// Rates (evaporation, speeds, deposits) are per second; multiply by `timestep` for one step.
 struct SimulationOptions {
   evaporation: f32,
-  // @todo: repellants
+  boundary: u32,
+  // lerp between the trail map and the blurred map
+  diffusion: f32,
+  // simulated seconds per step
+  timestep: f32,
+  // Agent positions span the unit square, while distances are measured in units of the world's
+  // height. Dividing a distance by `world_size` converts it to a change in position.
+  world_size: vec2<f32>,
 }
 
+// must match `BoundaryMode` in options.rs
+const BOUNDARY_WRAP: u32 = 0u;
+const BOUNDARY_REFLECT: u32 = 1u;
+const BOUNDARY_RESPAWN: u32 = 2u;
+const BOUNDARY_STICKY: u32 = 3u;
+
 struct Agent {
   pos: vec2<f32>,
+  // in units per second
+  velocity: vec2<f32>,
   angle: f32,
+  // the slot of the agent's species
+  species: u32,
+  // the distance moved since the agent last reoriented, for `MOVEMENT_LEVY`
+  run: f32,
+  // for `LIFECYCLE`
+  energy: f32,
 }
 
 struct Species {
   color: vec3<f32>,
   speed: f32,
-  turn_speed: f32,
-  view_distance: f32,
-  field_of_view: f32,
+  // the angle between the front sensor and the outermost ones
+  sensor_angle: f32,
+  // the distance between an agent and its sensors
+  sensor_offset: f32,
+  sensor_count: u32,
+  // the width of the square of texels each sensor averages
+  sensor_size: u32,
+  // how fast agents turn toward an outermost sensor
+  rotation_angle: f32,
+  // selects the layer (slot / 4) and channel (slot % 4) of the species' trail
+  slot: u32,
+  // the trail strength each agent adds per second
+  deposit: f32,
+  // the temperature of `STEERING_SOFTMAX`
+  temperature: f32,
+  // the width of the random perturbation of the heading every step
+  heading_noise: f32,
+  // the parameters of the movement model (see `MovementModel` in species.rs)
+  levy_exponent: f32,
+  levy_min_run: f32,
+  tumble_rate: f32,
+  tumble_sensitivity: f32,
+  persistence_length: f32,
+  // the parameters of `INERTIA` (see `Inertia` in species.rs)
+  mass: f32,
+  drag: f32,
+  trail_drag: f32,
+  max_speed: f32,
+  // the parameters of `LIFECYCLE` (see `Lifecycle` in lifecycle.rs)
+  initial_energy: f32,
+  drain: f32,
+  intake: f32,
+  split_energy: f32,
+}
+
+const MAX_SPECIES: u32 = #{MAX_SPECIES}u;
+const TRAIL_LAYERS: u32 = #{TRAIL_LAYERS}u;
+
+// One row of the interaction matrix: how strongly this species is attracted to (or, if negative,
+// repelled by) the trail of each species slot. Laid out like the channels of the trail map.
+struct Interactions {
+  weights: array<vec4<f32>, TRAIL_LAYERS>,
+}
+
+// the display color of each species slot
+struct Palette {
+  colors: array<vec4<f32>, MAX_SPECIES>,
+}
+
+struct SpawnPattern {
+  kind: u32,
+  radius: f32,
+  center: vec2<f32>,
+  slot: u32,
+  // the species' agents are `agents[start..start + count]`
+  start: u32,
+  count: u32,
+  // agents before this index (relative to `start`) survived a resize and are left alone
+  first_agent: u32,
 }
 
+// must match `GpuSpawnPattern::new` in species.rs
+const SPAWN_DISC: u32 = 0u;
+const SPAWN_UNIFORM: u32 = 1u;
+const SPAWN_BURST: u32 = 2u;
+const SPAWN_RING_OUTWARD: u32 = 3u;
+const SPAWN_RING_INWARD: u32 = 4u;
+const SPAWN_RANDOM_HEADING: u32 = 5u;
+const SPAWN_GRID: u32 = 6u;
+const SPAWN_IMAGE: u32 = 7u;
+
 @group(0) @binding(0)
 var<storage, read_write> agents: array<Agent>; // someday: bind write-only (https://github.com/gfx-rs/wgpu/issues/2897)
+// the qualities and interaction matrix row of every species, indexed by slot
 @group(0) @binding(1)
-var<uniform> species: Species;
+var<storage, read> all_species: array<Species, MAX_SPECIES>;
+@group(0) @binding(2)
+var<storage, read> all_interactions: array<Interactions, MAX_SPECIES>;
+
+// the agents simulated by this dispatch
+struct AgentRange {
+  start: u32,
+  count: u32,
+  // the slot of the first species in the range
+  slot: u32,
+}
+
+@group(0) @binding(3)
+var<uniform> batch: AgentRange;
+
+// The population of a species with a lifecycle, which changes on the GPU. Must match
+// `GpuPopulation` in lifecycle.rs.
+struct Population {
+  // the indirect dispatch that runs one invocation per live agent
+  workgroups: vec3<u32>,
+  // the species' agents `start..start + live` are alive
+  live: u32,
+  // the agents that survive or are born during a step, up to the capacity
+  next: u32,
+  // the size of the species' range of agents, or 0 for species without a lifecycle
+  capacity: u32,
+  // the agents that survive a step, which are packed before the offspring
+  survivors: u32,
+}
+
+@group(0) @binding(4)
+var<storage, read_write> population: array<Population, MAX_SPECIES>;
+// where `compact` packs the agents of species with a lifecycle
+@group(0) @binding(5)
+var<storage, read_write> next_agents: array<Agent>;
+// the survivors and offspring of each workgroup of agents, see `tally`
+@group(0) @binding(6)
+var<storage, read_write> tallies: array<vec2<u32>>;
 
+// Returns the number of agents in the batch. A batch of a species with a lifecycle holds just that
+// species, and only its live agents are simulated.
+fn batch_agents() -> u32 {
+#ifdef LIFECYCLE
+  return min(population[batch.slot].live, population[batch.slot].capacity);
+#else
+  return batch.count;
+#endif
+}
+
+// the species of the agent being simulated, see `load_species`
+var<private> species: Species;
+var<private> interactions: Interactions;
+
+fn load_species(slot: u32) {
+  species = all_species[slot];
+  interactions = all_interactions[slot];
+}
+
+// the image and pattern the species being initialized spawns from
+@group(1) @binding(0)
+var t_spawn: texture_2d<f32>;
+@group(1) @binding(1)
+var<uniform> spawn: SpawnPattern;
+
+// a single layer of the trail map
 @group(1) @binding(0)
 var t_trails_prev: texture_2d<f32>;
 @group(1) @binding(1)
 var s_trails_prev: sampler;
 
+// every layer of the trail map
+@group(1) @binding(0)
+var t_trails: texture_2d_array<f32>;
+@group(1) @binding(1)
+var s_trails: sampler;
+
+// The obstacle mask, bound next to either view of the trail map. See `Obstacles` in trail.rs.
+@group(1) @binding(2)
+var t_obstacles: texture_2d<f32>;
+
+// Mid-grey (0.5 in sRGB) in linear space, which is how the mask is loaded: the GPU decodes sRGB
+// masks.
+const OBSTACLE_THRESHOLD: f32 = 0.21404;
+
+// Whether the world position `pos` lies in an obstacle, i.e. the mask is darker than mid-grey
+// there.
+fn is_obstacle(pos: vec2<f32>) -> bool {
+  let texel = textureLoad(t_obstacles, world_to_tex(vec2<u32>(textureDimensions(t_obstacles)), pos), 0);
+  return dot(texel.rgb, vec3<f32>(0.2126, 0.7152, 0.0722)) < OBSTACLE_THRESHOLD;
+}
+
+// The deposits of the species in a single layer of the trail map, four channels per texel, as
+// fixed point numbers so that they can be accumulated atomically. See `DepositBuffer` in trail.rs.
 @group(2) @binding(0)
-var t_trails_next: texture_storage_2d<rgba8unorm, write>;
+var<storage, read_write> deposits: array<atomic<u32>>;
+
+// The texels of the layer being blurred, kept by the horizontal blur pass for the vertical one.
+@group(2) @binding(1)
+var<storage, read_write> unblurred: array<vec4<f32>>;
+
+// Deposits are accumulated in steps of 1 / DEPOSIT_SCALE, up to 2^32 / DEPOSIT_SCALE (about 4
+// million) per texel and channel in one step, beyond which they saturate. `project` dithers each
+// agent's deposit, so deposits smaller than a step still add up on average.
+const DEPOSIT_SCALE: f32 = 1024.0;
 
 @group(3) @binding(0)
 var<uniform> random_seed: u32;
@@ -38,61 +222,431 @@ var<uniform> random_seed: u32;
 @group(4) @binding(0)
 var<uniform> options: SimulationOptions;
 
+// must match `MAX_FOOD_SOURCES` in food.rs
+const MAX_FOOD_SOURCES: u32 = 64u;
+
+// An attractant, or a repellent if `strength` is negative. See `FoodSource` in food.rs.
+struct FoodSource {
+  center: vec2<f32>,
+  radius: f32,
+  falloff: f32,
+  // padded so that arrays of sources have a uniform-compatible stride
+  @size(16) strength: f32,
+}
+
+struct FoodSources {
+  @size(16) count: u32,
+  sources: array<FoodSource, MAX_FOOD_SOURCES>,
+}
+
+@group(4) @binding(1)
+var<uniform> food: FoodSources;
+
+// The nutrient field painted with the brush, one value per texel of the trail map. See
+// `NutrientBindGroup` in food.rs.
+@group(2) @binding(0)
+var<storage, read_write> nutrient_map: array<f32>;
+
+// Sums the strengths of the food sources and the painted nutrient field at the world position `pos`.
+fn nutrients(pos: vec2<f32>) -> f32 {
+  let dims = vec2<u32>(textureDimensions(t_trails));
+  let texel = world_to_tex(dims, pos);
+  var total = nutrient_map[texel.y * dims.x + texel.x];
+  for (var i = 0u; i < min(food.count, MAX_FOOD_SOURCES); i++) {
+    let source = food.sources[i];
+    var delta = pos - source.center;
+    if (options.boundary == BOUNDARY_WRAP) {
+      // measure across the seam
+      delta -= round(delta);
+    }
+    let dist = length(delta * options.world_size);
+    // full strength within the radius, fading to nothing over the falloff
+    let fade = 1.0 - clamp((dist - source.radius) / max(source.falloff, 1e-6), 0.0, 1.0);
+    total += source.strength * fade;
+  }
+  return total;
+}
+
 const TWO_PI: f32 = 6.28318530718;
 
+// Every agent entry point runs one invocation per agent, in workgroups of `WORKGROUP_SIZE`.
+const WORKGROUP_SIZE: u32 = #{WORKGROUP_SIZE}u;
+
+// separate the random numbers drawn by `init` and `project` from those drawn by `update`
+const INIT_STREAM: u32 = 0x1e1717u;
+const PROJECT_STREAM: u32 = 0x9e0ec7u;
+
 @compute
-@workgroup_size(256, 1, 1)
+@workgroup_size(#{WORKGROUP_SIZE}, 1, 1)
 // Initializes the simulation.
-fn init(@builtin(local_invocation_index) local_id: u32,
-        @builtin(num_workgroups) num_workgroups: vec3<u32>)
-{
+fn init(@builtin(global_invocation_id) id: vec3<u32>) {
+  if (id.x < spawn.first_agent || id.x >= spawn.count) {
+    return;
+  }
+  let index = spawn.start + id.x;
+
   seed(random_seed);
-  seed(local_id);
-  seed(u32(species.color.r * 255.0));
-  seed(u32(species.color.g * 255.0));
-  seed(u32(species.color.b * 255.0));
-
-  let total_kernels: u32 = num_workgroups.x * num_workgroups.y * num_workgroups.z;
-  let total_agents: u32 = arrayLength(&agents);
-  let agents_per_kernel = (total_agents + (total_kernels - 1u)) / total_kernels;
-
-  let start = agents_per_kernel * local_id;
-  for (var index = start; index < min(start + agents_per_kernel, total_agents); index++) {
-    let r = 0.5 * clamp(sqrt(rand_f32()), 0.0, 1.0);
-    let t = rand_f32() * TWO_PI;
-    var agent: Agent;
-    agent.pos = 0.5 + r * vec2<f32>(cos(t), sin(t));
-    agent.angle = atan2(0.5 - agent.pos.y, 0.5 - agent.pos.x);
-    agents[index] = agent;
+  // initialization reuses the seed of the next step, but mixes in `INIT_STREAM` so that its random
+  // numbers differ from those of that step's update
+  seed(INIT_STREAM);
+  seed(index);
+  var agent = spawn_agent(id.x, spawn.count);
+  agent.species = spawn.slot;
+  agent.energy = all_species[spawn.slot].initial_energy;
+  agents[index] = agent;
+}
+
+// the maximum number of rejection sampling attempts when spawning from an image
+const SPAWN_IMAGE_MAX_TRIES: u32 = 64u;
+
+// Places an agent according to the species' spawn pattern.
+fn spawn_agent(index: u32, total_agents: u32) -> Agent {
+  var agent: Agent;
+  // most patterns face a random direction
+  agent.angle = rand_f32() * TWO_PI;
+  switch (spawn.kind) {
+    case 0u, 5u: { // SPAWN_DISC, SPAWN_RANDOM_HEADING
+      let r = spawn.radius * clamp(sqrt(rand_f32()), 0.0, 1.0);
+      let t = rand_f32() * TWO_PI;
+      agent.pos = spawn.center + r * vec2<f32>(cos(t), sin(t)) / options.world_size;
+      if (spawn.kind == SPAWN_DISC) {
+        agent.angle = t + 0.5 * TWO_PI;
+      }
+    }
+    case 1u: { // SPAWN_UNIFORM
+      agent.pos = vec2<f32>(rand_f32(), rand_f32());
+    }
+    case 2u: { // SPAWN_BURST
+      agent.pos = spawn.center;
+    }
+    case 3u, 4u: { // SPAWN_RING_OUTWARD, SPAWN_RING_INWARD
+      let t = rand_f32() * TWO_PI;
+      agent.pos = spawn.center + spawn.radius * vec2<f32>(cos(t), sin(t)) / options.world_size;
+      agent.angle = t;
+      if (spawn.kind == SPAWN_RING_INWARD) {
+        agent.angle += 0.5 * TWO_PI;
+      }
+    }
+    case 6u: { // SPAWN_GRID
+      // square cells, so there are more columns than rows in wide worlds
+      let aspect = options.world_size.x / options.world_size.y;
+      let cols = u32(ceil(sqrt(f32(total_agents) * aspect)));
+      let rows = (total_agents + cols - 1u) / cols;
+      agent.pos = (vec2<f32>(f32(index % cols), f32(index / cols)) + 0.5) / vec2<f32>(f32(cols), f32(rows));
+    }
+    case 7u: { // SPAWN_IMAGE
+      agent.pos = sample_spawn_image();
+    }
+    default: {
+      agent.pos = vec2<f32>(0.5);
+    }
+  }
+  agent.pos = clamp(agent.pos, vec2<f32>(0.0), vec2<f32>(1.0));
+  return agent;
+}
+
+// Rejection-samples a position with probability proportional to the luminance of the spawn image,
+// which is bound during initialization.
+fn sample_spawn_image() -> vec2<f32> {
+  let dims = vec2<u32>(textureDimensions(t_spawn));
+  var pos = vec2<f32>(rand_f32(), rand_f32());
+  for (var i = 0u; i < SPAWN_IMAGE_MAX_TRIES; i++) {
+    let texel = textureLoad(t_spawn, world_to_tex(dims, pos), 0);
+    let luminance = dot(texel.rgb, vec3<f32>(0.2126, 0.7152, 0.0722)) * texel.a;
+    if (rand_f32() < luminance) {
+      break;
+    }
+    pos = vec2<f32>(rand_f32(), rand_f32());
   }
+  return pos;
 }
 
-const STEER_NUM_SAMPLES: u32 = 3u;
+// Behavior modules (see `Hook` in behavior.rs) replace the hooks below by defining the matching
+// `BEHAVIOR_*` shader def and a function with the same signature.
 
-// returns the new heading for an agent
-fn steer(agent: Agent) -> f32 {
-  let angle_delta = (species.turn_speed * 2.0) / f32(STEER_NUM_SAMPLES - 1u);
-  var angle = agent.angle - species.turn_speed;
-  var t = agent.angle;
-  var t_sim = 0.0;
-  for (var i = 0u; i < STEER_NUM_SAMPLES; i++) {
-    let dir = vec2<f32>(cos(angle), sin(angle));
-    let wc =  species.view_distance * dir + agent.pos;
-    // @todo: is this needed? we could skip and just take black if that is faster
-    if (wc.x < 0.0 || wc.y < 0.0 || wc.x >= 1.0 || wc.y >= 1.0) {
-      continue;
+#ifndef BEHAVIOR_SENSE
+// Scores a texel by weighing the trail of every species according to the interaction matrix.
+fn sense(tc: vec2<u32>) -> f32 {
+  var score = 0.0;
+  for (var layer = 0u; layer < TRAIL_LAYERS; layer++) {
+    score += dot(interactions.weights[layer], textureLoad(t_trails, tc, i32(layer), 0));
+  }
+  return score;
+}
+#endif
+
+// Averages `sense` over the `sensor_size` by `sensor_size` square of texels centered on `tc`.
+fn sense_footprint(tc: vec2<u32>) -> f32 {
+  let dims = vec2<i32>(textureDimensions(t_trails));
+  let size = max(species.sensor_size, 1u);
+  let corner = vec2<i32>(tc) - i32(size / 2u);
+  var total = 0.0;
+  var samples = 0u;
+  for (var y = 0u; y < size; y++) {
+    for (var x = 0u; x < size; x++) {
+      var texel = corner + vec2<i32>(vec2<u32>(x, y));
+      if (options.boundary == BOUNDARY_WRAP) {
+        // sense across the seam
+        texel = (texel + dims) % dims;
+      } else if (any(texel < vec2<i32>(0)) || any(texel >= dims)) {
+        continue;
+      }
+      total += sense(vec2<u32>(texel));
+      samples++;
     }
-    let tc = world_to_tex(vec2<u32>(textureDimensions(t_trails_prev)), wc);
-    let s = textureLoad(t_trails_prev, tc, 0).rgb;
-    let d = dot(species.color, s) * 2.0 - 1.0;
-    if (d > t_sim) {
-      t_sim = d;
-      t = angle;
+  }
+  return total / f32(max(samples, 1u));
+}
+
+// must match `MAX_SENSORS` in species.rs
+const MAX_SENSORS: u32 = 16u;
+
+// What sensors outside the world or inside an obstacle sense.
+const NOTHING_SENSED: f32 = -1e30;
+
+// what each sensor of the agent being steered senses
+var<private> sensed: array<f32, MAX_SENSORS>;
+
+// The position of sensor `i` of `count`, from -1 (the leftmost sensor) to 1 (the rightmost). A
+// single sensor faces forward.
+fn sensor_position(i: u32, count: u32) -> f32 {
+  if (count < 2u) {
+    return 0.0;
+  }
+  return 2.0 * f32(i) / f32(count - 1u) - 1.0;
+}
+
+// Each steering mode (see `SteeringMode` in species.rs) picks how far to turn from what the sensors
+// sense, from -1 (fully toward the leftmost sensor) to 1 (fully toward the rightmost).
+
+#ifdef STEERING_JONES
+fn choose_turn(count: u32) -> f32 {
+  let left = sensed[0];
+  let front = sensed[count / 2u];
+  let right = sensed[count - 1u];
+  if (front > left && front > right) {
+    return 0.0;
+  }
+  if (front < left && front < right) {
+    return select(-1.0, 1.0, rand_f32() < 0.5);
+  }
+  if (left < right) {
+    return 1.0;
+  }
+  if (right < left) {
+    return -1.0;
+  }
+  return 0.0;
+}
+#endif
+
+#ifdef STEERING_WEIGHTED_RANDOM
+fn choose_turn(count: u32) -> f32 {
+  var total = 0.0;
+  for (var i = 0u; i < count; i++) {
+    total += max(sensed[i], 0.0);
+  }
+  if (total <= 0.0) {
+    return 0.0;
+  }
+  var pick = rand_f32() * total;
+  for (var i = 0u; i < count; i++) {
+    pick -= max(sensed[i], 0.0);
+    if (pick <= 0.0 && sensed[i] > 0.0) {
+      return sensor_position(i, count);
     }
-    angle += angle_delta;
   }
-  return t;
+  return 0.0;
+}
+#endif
+
+#ifdef STEERING_SOFTMAX
+fn choose_turn(count: u32) -> f32 {
+  // subtract the largest value so that the exponentials can't overflow
+  var largest = NOTHING_SENSED;
+  for (var i = 0u; i < count; i++) {
+    largest = max(largest, sensed[i]);
+  }
+  if (largest <= NOTHING_SENSED) {
+    return 0.0;
+  }
+  let temperature = max(species.temperature, 1e-6);
+  var total = 0.0;
+  for (var i = 0u; i < count; i++) {
+    sensed[i] = exp((sensed[i] - largest) / temperature);
+    total += sensed[i];
+  }
+  var pick = rand_f32() * total;
+  for (var i = 0u; i < count; i++) {
+    pick -= sensed[i];
+    if (pick <= 0.0) {
+      return sensor_position(i, count);
+    }
+  }
+  return sensor_position(count - 1u, count);
+}
+#endif
+
+#ifndef STEERING_JONES
+#ifndef STEERING_WEIGHTED_RANDOM
+#ifndef STEERING_SOFTMAX
+// Turns toward the sensor that senses the most, or between the sensors that tie for it, so an agent
+// that senses the same everywhere goes straight. Also goes straight when no sensor is in the world.
+fn choose_turn(count: u32) -> f32 {
+  var best = NOTHING_SENSED;
+  for (var i = 0u; i < count; i++) {
+    best = max(best, sensed[i]);
+  }
+  if (best <= NOTHING_SENSED) {
+    return 0.0;
+  }
+  var turn = 0.0;
+  var ties = 0.0;
+  for (var i = 0u; i < count; i++) {
+    if (sensed[i] == best) {
+      turn += sensor_position(i, count);
+      ties += 1.0;
+    }
+  }
+  return turn / ties;
+}
+#endif
+#endif
+#endif
+
+// Returns what a sensor at `pos` (in world coordinates) senses, or `NOTHING_SENSED` if it is
+// outside the world or inside an obstacle.
+fn sense_at(pos: vec2<f32>) -> f32 {
+  var wc = pos;
+  if (options.boundary == BOUNDARY_WRAP) {
+    // sense across the seam
+    wc = fract(wc);
+  } else if (wc.x < 0.0 || wc.y < 0.0 || wc.x >= 1.0 || wc.y >= 1.0) {
+    return NOTHING_SENSED;
+  }
+  // obstacles block sensing
+  if (is_obstacle(wc)) {
+    return NOTHING_SENSED;
+  }
+  let dims = vec2<u32>(textureDimensions(t_trails));
+  return sense_footprint(world_to_tex(dims, wc)) + nutrients(wc);
+}
+
+#ifndef BEHAVIOR_STEER
+// Returns the new heading for an agent, turned according to the species' steering mode.
+fn steer(agent: Agent) -> f32 {
+  if (species.sensor_angle <= 0.0) {
+    // every sensor senses the same
+    return agent.angle;
+  }
+  let count = clamp(species.sensor_count, 1u, MAX_SENSORS);
+  for (var i = 0u; i < count; i++) {
+    let angle = agent.angle + species.sensor_angle * sensor_position(i, count);
+    let dir = vec2<f32>(cos(angle), sin(angle));
+    sensed[i] = sense_at(species.sensor_offset * dir / options.world_size + agent.pos);
+  }
+  return agent.angle + choose_turn(count) * species.rotation_angle * options.timestep;
+}
+#endif
+
+// Each movement model (see `MovementModel` in species.rs) may turn an agent to a new heading
+// before it moves `step` units.
+
+#ifdef MOVEMENT_LEVY
+fn reorient(agent: ptr<function, Agent>, step: f32) {
+  (*agent).run += step;
+  // Run lengths l >= levy_min_run follow l^-levy_exponent, so a run that has lasted l so far ends
+  // within the next step with probability (levy_exponent - 1) * step / l.
+  let run = max((*agent).run, species.levy_min_run);
+  if ((*agent).run >= species.levy_min_run
+      && rand_f32() < (species.levy_exponent - 1.0) * step / run) {
+    (*agent).angle = rand_f32() * TWO_PI;
+    (*agent).run = 0.0;
+  }
+}
+#endif
+
+#ifdef MOVEMENT_RUN_AND_TUMBLE
+fn reorient(agent: ptr<function, Agent>, step: f32) {
+  let heading = vec2<f32>(cos((*agent).angle), sin((*agent).angle));
+  let here = sense_at((*agent).pos);
+  let ahead = sense_at((*agent).pos + species.sensor_offset * heading / options.world_size);
+  var gradient = 0.0;
+  if (here > NOTHING_SENSED && ahead > NOTHING_SENSED && species.sensor_offset > 0.0) {
+    gradient = (ahead - here) / species.sensor_offset;
+  }
+  // tumble less often while things improve
+  let rate = species.tumble_rate * exp(clamp(-species.tumble_sensitivity * gradient, -40.0, 40.0));
+  if (rand_f32() < rate * options.timestep) {
+    (*agent).angle = rand_f32() * TWO_PI;
+  }
+}
+#endif
+
+#ifdef MOVEMENT_CORRELATED_RANDOM_WALK
+fn reorient(agent: ptr<function, Agent>, step: f32) {
+  // rotational diffusion, whose variance grows by 2 / persistence_length per unit moved
+  let variance = 2.0 * step / max(species.persistence_length, 1e-6);
+  (*agent).angle += sqrt(variance) * rand_normal();
+}
+#endif
+
+#ifndef MOVEMENT_LEVY
+#ifndef MOVEMENT_RUN_AND_TUMBLE
+#ifndef MOVEMENT_CORRELATED_RANDOM_WALK
+fn reorient(agent: ptr<function, Agent>, step: f32) {}
+#endif
+#endif
+#endif
+
+#ifdef INERTIA
+// Returns the velocity of an agent that wants to cruise along `heading`. Drag pulls the velocity
+// toward the cruising velocity, and the trail under the agent drags it toward rest.
+fn accelerate(agent: Agent, heading: vec2<f32>) -> vec2<f32> {
+  let trail = max(sense_at(agent.pos), 0.0);
+  let drag = max(species.drag + species.trail_drag * trail, 0.0);
+  if (drag <= 0.0) {
+    return agent.velocity;
+  }
+  // integrate exactly, since the drag can be stiff
+  let cruising = species.drag * species.speed * heading / drag;
+  let decay = exp(-drag * options.timestep / max(species.mass, 1e-6));
+  let velocity = cruising + (agent.velocity - cruising) * decay;
+  let speed = length(velocity);
+  if (speed > species.max_speed) {
+    return velocity * species.max_speed / speed;
+  }
+  return velocity;
+}
+#endif
+
+#ifndef BEHAVIOR_MOVE
+// Advances an agent according to the species' movement model, along its heading or, with
+// `INERTIA`, by its velocity. The boundary is applied afterwards.
+fn move_agent(agent: Agent) -> Agent {
+  var moved = agent;
+#ifdef INERTIA
+  reorient(&moved, length(agent.velocity) * options.timestep);
+  moved.velocity = accelerate(agent, vec2<f32>(cos(moved.angle), sin(moved.angle)));
+  // face where the agent is going, unless it is at rest
+  if (any(moved.velocity != vec2<f32>(0.0))) {
+    moved.angle = atan2(moved.velocity.y, moved.velocity.x);
+  }
+#else
+  reorient(&moved, species.speed * options.timestep);
+  moved.velocity = species.speed * vec2<f32>(cos(moved.angle), sin(moved.angle));
+#endif
+  moved.pos += moved.velocity * options.timestep / options.world_size;
+  return moved;
+}
+#endif
+
+#ifndef BEHAVIOR_DEPOSIT
+// Returns the trail strength an agent adds to the texel it occupies per second.
+fn deposit(agent: Agent) -> f32 {
+  return species.deposit;
 }
+#endif
 
 // converts world coordinates to texel index, assuming pos.x and pos.y are in [0.0, 1.0].
 fn world_to_tex(dims: vec2<u32>, pos: vec2<f32>) -> vec2<u32> {
@@ -100,103 +654,422 @@ fn world_to_tex(dims: vec2<u32>, pos: vec2<f32>) -> vec2<u32> {
   return vec2<u32>(clamp(floor(scaled), vec2<f32>(0.0), vec2<f32>(dims - 1u)));
 }
 
+// Keeps `pos` inside the world according to `options.boundary`, returning the new heading.
+fn apply_boundary(pos: ptr<function, vec2<f32>>, heading: vec2<f32>) -> vec2<f32> {
+  let p = *pos;
+  let outside = p.x < 0.0 || p.y < 0.0 || p.x > 1.0 || p.y > 1.0;
+  if (options.boundary == BOUNDARY_WRAP) {
+    *pos = fract(p);
+    return heading;
+  }
+  if (options.boundary == BOUNDARY_RESPAWN) {
+    if (outside) {
+      *pos = vec2<f32>(rand_f32(), rand_f32());
+      let t = rand_f32() * TWO_PI;
+      return vec2<f32>(cos(t), sin(t));
+    }
+    return heading;
+  }
+  *pos = clamp(p, vec2<f32>(0.0), vec2<f32>(1.0));
+  if (options.boundary == BOUNDARY_STICKY) {
+    // keep pushing into the wall until steering turns the agent away
+    return heading;
+  }
+
+  // BOUNDARY_REFLECT
+  var edge_normal = vec2<f32>(0.0, 0.0);
+  if (p.x < 0.0) {
+    edge_normal.x = 1.0;
+  }
+  if (p.x > 1.0) {
+    edge_normal.x = -1.0;
+  }
+  if (p.y < 0.0) {
+    edge_normal.y = 1.0;
+  }
+  if (p.y > 1.0) {
+    edge_normal.y = -1.0;
+  }
+  return heading - 2.0 * edge_normal * dot(heading, edge_normal);
+}
+
 @compute
-@workgroup_size(256, 1, 1)
+@workgroup_size(#{WORKGROUP_SIZE}, 1, 1)
 // Updates the simulation.
-fn update(@builtin(local_invocation_index) local_id: u32,
-          @builtin(num_workgroups) num_workgroups: vec3<u32>)
-{
+fn update(@builtin(global_invocation_id) id: vec3<u32>) {
+  if (id.x >= batch_agents()) {
+    return;
+  }
+  let index = batch.start + id.x;
+
   seed(random_seed);
-  seed(local_id);
+  seed(index);
 
-  let total_kernels: u32 = num_workgroups.x * num_workgroups.y * num_workgroups.z;
-  let total_agents: u32 = arrayLength(&agents);
-  let agents_per_kernel = (total_agents + (total_kernels - 1u)) / total_kernels;
+  var agent: Agent = agents[index];
+  load_species(agent.species);
+  let prev_pos = agent.pos;
+  agent.angle = steer(agent);
+  agent = move_agent(agent);
+  var heading = vec2<f32>(cos(agent.angle), sin(agent.angle));
+  heading = apply_boundary(&agent.pos, heading);
+  // Agents can't enter obstacles, so stay put and turn to a random heading. Agents already inside
+  // one (e.g. because it was painted over them) are free to leave.
+  if (is_obstacle(agent.pos) && !is_obstacle(prev_pos)) {
+    agent.pos = prev_pos;
+    let t = rand_f32() * TWO_PI;
+    heading = vec2<f32>(cos(t), sin(t));
+  }
+  // slightly perturb the heading, a random walk that spreads with the square root of time
+  let noise = species.heading_noise * sqrt(options.timestep) * (rand_f32() - 0.5);
+  agent.angle = atan2(heading.y, heading.x) + noise;
+  // the boundary, obstacles and noise turn the agent's motion along with its heading
+  agent.velocity = length(agent.velocity) * vec2<f32>(cos(agent.angle), sin(agent.angle));
+#ifdef LIFECYCLE
+  // living costs energy, and food (or repellent) where the agent is adds (or takes) some
+  agent.energy += (species.intake * nutrients(agent.pos) - species.drain) * options.timestep;
+#endif
+  agents[index] = agent;
+}
 
-  let dims = textureDimensions(t_trails_prev);
+// The lifecycle entry points run on batches of a single species with a lifecycle, after `update`.
+// They pack the survivors, then the offspring, in the order of their parents, so that a step packs
+// the same agents into the same places every time it is simulated.
 
-  let start = agents_per_kernel * local_id;  
-  for (var index = start; index < min(start + agents_per_kernel, total_agents); index++) {
-    var agent: Agent = agents[index];
+var<workgroup> scan: array<vec2<u32>, #{WORKGROUP_SIZE}>;
 
-    agent.angle = steer(agent);
-    var heading = vec2<f32>(cos(agent.angle), sin(agent.angle));
-    agent.pos += species.speed * heading;
-    
-    var edge_normal = vec2<f32>(0.0, 0.0);
-    if (agent.pos.x < 0.0) {
-      agent.pos.x = 0.0;
-      edge_normal.x = 1.0;
-    }
-    if (agent.pos.x > 1.0) {
-      agent.pos.x = 1.0;
-      edge_normal.x = -1.0;
-    }
-    if (agent.pos.y < 0.0) {
-      agent.pos.y = 0.0;
-      edge_normal.y = 1.0;
-    }
-    if (agent.pos.y > 1.0) {
-      agent.pos.y = 1.0;
-      edge_normal.y = -1.0;
+// Returns whether the `index`th agent of the batch survives and whether it splits.
+fn fate(index: u32) -> vec2<u32> {
+  let live = min(population[batch.slot].live, population[batch.slot].capacity);
+  if (index >= live) {
+    return vec2<u32>(0u);
+  }
+  let energy = agents[batch.start + index].energy;
+  if (energy <= 0.0) {
+    return vec2<u32>(0u);
+  }
+  return vec2<u32>(1u, select(0u, 1u, energy >= all_species[batch.slot].split_energy));
+}
+
+// Returns the sum of `value` over the invocations of the workgroup before this one, and leaves the
+// sum over every invocation in `scan[WORKGROUP_SIZE - 1u]`. Must be called by every
+// invocation.
+fn workgroup_scan(local: u32, value: vec2<u32>) -> vec2<u32> {
+  scan[local] = value;
+  for (var offset = 1u; offset < WORKGROUP_SIZE; offset *= 2u) {
+    workgroupBarrier();
+    var sum = scan[local];
+    if (local >= offset) {
+      sum += scan[local - offset];
     }
-    heading -= 2.0 * edge_normal * dot(heading, edge_normal);
-    // slightly perturb the heading by up to 0.1 degrees
-    agent.angle = atan2(heading.y, heading.x) + 0.00174533 * (rand_f32() - 0.5);
-    agents[index] = agent;
+    workgroupBarrier();
+    scan[local] = sum;
+  }
+  workgroupBarrier();
+  return scan[local] - value;
+}
+
+// The tally of the first workgroup of the batch's species. Species are laid out by slot, so
+// offsetting by the slot keeps species that share a workgroup's worth of agents apart.
+fn first_tally() -> u32 {
+  return batch.start / WORKGROUP_SIZE + batch.slot;
+}
+
+@compute
+@workgroup_size(#{WORKGROUP_SIZE}, 1, 1)
+// Counts the survivors and offspring of each workgroup of agents.
+fn tally(
+  @builtin(global_invocation_id) id: vec3<u32>,
+  @builtin(local_invocation_index) local: u32,
+  @builtin(workgroup_id) group: vec3<u32>,
+) {
+  workgroup_scan(local, fate(id.x));
+  // the dispatch may be sized for a population from before the capacity shrank
+  let live = min(population[batch.slot].live, population[batch.slot].capacity);
+  if (local == 0u && group.x * WORKGROUP_SIZE < live) {
+    tallies[first_tally() + group.x] = scan[WORKGROUP_SIZE - 1u];
+  }
+}
+
+@compute
+@workgroup_size(#{WORKGROUP_SIZE}, 1, 1)
+// Replaces the tally of every workgroup of agents with the sum of the tallies before it, and sizes
+// the species' next population. Runs a single workgroup per species.
+fn scan_tallies(@builtin(local_invocation_index) local: u32) {
+  let live = min(population[batch.slot].live, population[batch.slot].capacity);
+  let groups = (live + WORKGROUP_SIZE - 1u) / WORKGROUP_SIZE;
+  // each invocation sums a chunk of the tallies
+  let chunk = (groups + WORKGROUP_SIZE - 1u) / WORKGROUP_SIZE;
+  let first = first_tally() + min(local * chunk, groups);
+  let last = first_tally() + min((local + 1u) * chunk, groups);
+  var sum = vec2<u32>(0u);
+  for (var i = first; i < last; i++) {
+    sum += tallies[i];
+  }
+  var prefix = workgroup_scan(local, sum);
+  for (var i = first; i < last; i++) {
+    let tally = tallies[i];
+    tallies[i] = prefix;
+    prefix += tally;
+  }
+  if (local == 0u) {
+    let total = scan[WORKGROUP_SIZE - 1u];
+    population[batch.slot].survivors = total.x;
+    population[batch.slot].next = min(total.x + total.y, population[batch.slot].capacity);
   }
 }
 
+@compute
+@workgroup_size(#{WORKGROUP_SIZE}, 1, 1)
+// Packs the agents that have energy left into `next_agents`, and an offspring of every agent that
+// splits after the survivors, while the species has room for it. Offspring start where their
+// parent is, heading in a random direction.
+fn compact(
+  @builtin(global_invocation_id) id: vec3<u32>,
+  @builtin(local_invocation_index) local: u32,
+  @builtin(workgroup_id) group: vec3<u32>,
+) {
+  let agent_fate = fate(id.x);
+  let packed = workgroup_scan(local, agent_fate) + tallies[first_tally() + group.x];
+  if (agent_fate.x == 0u) {
+    return;
+  }
+  var parent = agents[batch.start + id.x];
+  // parents only split (and give away half their energy) when there is room for the offspring
+  let index = population[batch.slot].survivors + packed.y;
+  let splits = agent_fate.y == 1u && index < population[batch.slot].capacity;
+  if (splits) {
+    parent.energy *= 0.5;
+  }
+  // survivors never outnumber the live agents, so they always fit
+  next_agents[batch.start + packed.x] = parent;
+  if (!splits) {
+    return;
+  }
+
+  seed(random_seed);
+  // don't repeat the random numbers `update` drew for the parent
+  seed(~(batch.start + id.x));
+  var child = parent;
+  child.angle = rand_f32() * TWO_PI;
+  child.velocity = length(parent.velocity) * vec2<f32>(cos(child.angle), sin(child.angle));
+  child.run = 0.0;
+  next_agents[batch.start + index] = child;
+}
+
+@compute
+@workgroup_size(#{WORKGROUP_SIZE}, 1, 1)
+// Moves every species with a lifecycle on to the population `compact` packed, and sizes the
+// dispatches of its next step.
+fn census(@builtin(global_invocation_id) id: vec3<u32>) {
+  let slot = id.x;
+  if (slot >= MAX_SPECIES || population[slot].capacity == 0u) {
+    return;
+  }
+  let live = population[slot].next;
+  population[slot].live = live;
+  population[slot].workgroups = vec3<u32>((live + WORKGROUP_SIZE - 1u) / WORKGROUP_SIZE, 1u, 1u);
+}
 
 @compute
-@workgroup_size(256, 1, 1)
-// Projects the agents onto the new texture.
-fn project(@builtin(local_invocation_index) local_id: u32,
-           @builtin(num_workgroups) num_workgroups: vec3<u32>)
-{
-  let total_kernels: u32 = num_workgroups.x * num_workgroups.y * num_workgroups.z;
-  let total_agents: u32 = arrayLength(&agents);
-  let agents_per_kernel = (total_agents + (total_kernels - 1u)) / total_kernels;
+@workgroup_size(#{WORKGROUP_SIZE}, 1, 1)
+// Accumulates the deposits of the agents, which the vertical blur pass resolves into the trail map.
+fn project(@builtin(global_invocation_id) id: vec3<u32>) {
+  if (id.x >= batch_agents()) {
+    return;
+  }
+  let agent = agents[batch.start + id.x];
+  if (is_obstacle(agent.pos)) {
+    return;
+  }
+  load_species(agent.species);
 
-  let start = agents_per_kernel * local_id;
-  let dims = vec2<u32>(textureDimensions(t_trails_next));
-  for (var index = start; index < min(start + agents_per_kernel, total_agents); index++) {
-    let agent: Agent = agents[index];
-    let texel = world_to_tex(dims, agent.pos);
-    textureStore(t_trails_next, texel, vec4(species.color, 1.0));
+  seed(random_seed);
+  seed(PROJECT_STREAM);
+  seed(batch.start + id.x);
+  let dims = vec2<u32>(textureDimensions(t_trails));
+  let amount = u32(max(deposit(agent) * options.timestep, 0.0) * DEPOSIT_SCALE + rand_f32());
+  let texel = world_to_tex(dims, agent.pos);
+  let index = (texel.y * dims.x + texel.x) * 4u + agent.species % 4u;
+  // saturate instead of wrapping: whoever overflows the sum sets it to the maximum
+  let previous = atomicAdd(&deposits[index], amount);
+  if (previous + amount < previous) {
+    atomicMax(&deposits[index], 0xFFFFFFFFu);
   }
 }
 
 @group(3) @binding(0)
 var<uniform> direction: vec2<i32>;
 
-const BLUR_SAMPLE_COUNT: i32 = 4;
+// must match `MAX_KERNEL_RADIUS` in blur.rs
+const MAX_KERNEL_RADIUS: u32 = 15u;
 
-var<private> BLUR_OFFSETS : array<f32, 4> = array<f32, 4>(
-    -2.431625915613778,
-    -0.4862426846689484,
-    1.4588111840004858,
-    3.0
-);
+// A symmetric gaussian kernel, generated by `GpuKernel::new` in blur.rs. `weights` holds the
+// weights of the taps at offsets 0..=MAX_KERNEL_RADIUS, four to a vector.
+struct Kernel {
+  radius: u32,
+  weights: array<vec4<f32>, 4>,
+}
 
-var<private> BLUR_WEIGHTS : array<f32, 4> = array<f32, 4>(
-    0.24696196374528634,
-    0.34050702333458593,
-    0.30593582919679174,
-    0.10659518372333592
-);
+@group(3) @binding(1)
+var<uniform> kernel: Kernel;
 
+fn kernel_weight(offset: u32) -> f32 {
+    return kernel.weights[offset / 4u][offset % 4u];
+}
+
+// Samples the layer `offset` texels away from `uv` along `direction`.
+fn blur_sample(uv: vec2<f32>, offset: i32) -> vec4<f32> {
+    let dims = vec2<f32>(textureDimensions(t_trails_prev));
+    var sample_uv = uv + vec2<f32>(direction * offset) / dims;
+    // the sampler repeats, so only clamp (to the outermost texel centers) when the world doesn't wrap
+    if (options.boundary != BOUNDARY_WRAP) {
+        let half_texel = 0.5 / dims;
+        sample_uv = clamp(sample_uv, half_texel, 1.0 - half_texel);
+    }
+    return textureSample(t_trails_prev, s_trails_prev, sample_uv);
+}
+
+// Blurs a single layer of the trail map along `direction`.
+fn blur(uv: vec2<f32>) -> vec4<f32> {
+    var color = blur_sample(uv, 0) * kernel_weight(0u);
+    for (var i = 1u; i <= min(kernel.radius, MAX_KERNEL_RADIUS); i++) {
+        color += (blur_sample(uv, -i32(i)) + blur_sample(uv, i32(i))) * kernel_weight(i);
+    }
+    return color;
+}
+
+// The index of the texel being shaded in the (single layer) deposit and unblurred buffers.
+fn texel_index(position: vec4<f32>) -> u32 {
+    let dims = vec2<u32>(textureDimensions(t_trails_prev));
+    let texel = vec2<u32>(position.xy);
+    return texel.y * dims.x + texel.x;
+}
 
 @fragment
+// Blurs horizontally, keeping the unblurred texel for `resolve_fragment`.
 fn blur_fragment(in: FullscreenVertexOutput) -> @location(0) vec4<f32> {
-    let dims = vec2<f32>(textureDimensions(t_trails_prev));
+    unblurred[texel_index(in.position)] = blur_sample(in.uv, 0);
+    // trails never diffuse into obstacles
+    if (is_obstacle(in.uv)) {
+        return vec4<f32>(0.0);
+    }
+    return blur(in.uv);
+}
+
+@fragment
+// Blurs vertically, diffuses between the unblurred and blurred layer and evaporates, then adds (and
+// clears) the deposits accumulated by `project`.
+fn resolve_fragment(in: FullscreenVertexOutput) -> @location(0) vec4<f32> {
+    let index = texel_index(in.position);
+    var deposit = vec4<f32>(0.0);
+    for (var channel = 0u; channel < 4u; channel++) {
+        deposit[channel] = f32(atomicExchange(&deposits[index * 4u + channel], 0u)) / DEPOSIT_SCALE;
+    }
+    if (is_obstacle(in.uv)) {
+        return vec4<f32>(0.0);
+    }
+    let raw = unblurred[index];
+    // lerp by hand: naga mistypes `mix` here
+    let diffused = raw + (blur(in.uv) - raw) * options.diffusion;
+    // evaporate once per step, after both blur passes
+    return max(diffused - options.evaporation * options.timestep, vec4<f32>(0.0)) + deposit;
+}
+
+@group(3) @binding(0)
+var<uniform> palette: Palette;
+
+const OBSTACLE_COLOR: vec3<f32> = vec3<f32>(0.2, 0.2, 0.2);
+
+@fragment
+// Colorizes the trail of every species with its display color.
+fn display_fragment(in: FullscreenVertexOutput) -> @location(0) vec4<f32> {
     var color = vec3<f32>(0.0);
-    for (var i = 0; i < BLUR_SAMPLE_COUNT; i++)
-    {
-        let offset: vec2<f32> = vec2<f32>(direction) * BLUR_OFFSETS[i] / dims;
-        let weight: f32 = BLUR_WEIGHTS[i];
-        color += textureSample(t_trails_prev, s_trails_prev, in.uv + offset).rgb * weight;
-    }
-    color = clamp(color - options.evaporation, vec3<f32>(0.0), vec3<f32>(1.0));
-    return vec4(color, 1.0);
-}
\ No newline at end of file
+    for (var layer = 0u; layer < TRAIL_LAYERS; layer++) {
+        let trails = textureSample(t_trails, s_trails, in.uv, i32(layer));
+        for (var channel = 0u; channel < 4u; channel++) {
+            color += palette.colors[layer * 4u + channel].rgb * trails[channel];
+        }
+    }
+    if (is_obstacle(in.uv)) {
+        color = OBSTACLE_COLOR;
+    }
+    return vec4(clamp(color, vec3<f32>(0.0), vec3<f32>(1.0)), 1.0);
+}
+
+// must match the `BRUSH_*` constants in brush.rs
+const BRUSH_DEPOSIT: u32 = 0u;
+const BRUSH_NUTRIENT: u32 = 1u;
+const BRUSH_ERASE: u32 = 2u;
+
+// must match `MAX_BRUSH_STROKES` in brush.rs
+const MAX_BRUSH_STROKES: u32 = 16u;
+
+struct BrushStroke {
+  pos: vec2<f32>,
+  radius: f32,
+  strength: f32,
+  // the species whose chemical `BRUSH_DEPOSIT` deposits
+  slot: u32,
+  // padded so that arrays of strokes have a uniform-compatible stride
+  @size(12) mode: u32,
+}
+
+struct Brush {
+  @size(16) count: u32,
+  strokes: array<BrushStroke, MAX_BRUSH_STROKES>,
+}
+
+@group(3) @binding(0)
+var<uniform> brush: Brush;
+// the layer of the trail map being painted
+@group(3) @binding(1)
+var<uniform> brush_layer: u32;
+
+// The strength of a stroke at the world position `pos`, fading from full strength halfway out to
+// nothing at its radius.
+fn brush_coverage(stroke: BrushStroke, pos: vec2<f32>) -> f32 {
+  var delta = pos - stroke.pos;
+  if (options.boundary == BOUNDARY_WRAP) {
+    delta -= round(delta);
+  }
+  let dist = length(delta * options.world_size);
+  return 1.0 - smoothstep(0.5 * stroke.radius, max(stroke.radius, 1e-6), dist);
+}
+
+@compute
+@workgroup_size(#{WORKGROUP_SIZE}, 1, 1)
+// Paints the strokes into the nutrient field, one invocation per texel.
+fn brush_nutrients(@builtin(global_invocation_id) id: vec3<u32>) {
+  let dims = vec2<u32>(textureDimensions(t_trails));
+  if (id.x >= dims.x * dims.y) {
+    return;
+  }
+  let pos = (vec2<f32>(f32(id.x % dims.x), f32(id.x / dims.x)) + 0.5) / vec2<f32>(dims);
+  var value = nutrient_map[id.x];
+  for (var i = 0u; i < min(brush.count, MAX_BRUSH_STROKES); i++) {
+    let stroke = brush.strokes[i];
+    let coverage = brush_coverage(stroke, pos);
+    if (stroke.mode == BRUSH_NUTRIENT) {
+      value += stroke.strength * coverage;
+    } else if (stroke.mode == BRUSH_ERASE) {
+      value *= 1.0 - clamp(stroke.strength * coverage, 0.0, 1.0);
+    }
+  }
+  nutrient_map[id.x] = value;
+}
+
+@fragment
+// Paints the strokes into the layer `brush_layer` of the trail map.
+fn brush_trails_fragment(in: FullscreenVertexOutput) -> @location(0) vec4<f32> {
+  var trails = textureLoad(t_trails, vec2<i32>(in.position.xy), i32(brush_layer), 0);
+  for (var i = 0u; i < min(brush.count, MAX_BRUSH_STROKES); i++) {
+    let stroke = brush.strokes[i];
+    let coverage = brush_coverage(stroke, in.uv);
+    if (stroke.mode == BRUSH_DEPOSIT && stroke.slot / 4u == brush_layer) {
+      trails[stroke.slot % 4u] += stroke.strength * coverage;
+    } else if (stroke.mode == BRUSH_ERASE) {
+      trails *= 1.0 - clamp(stroke.strength * coverage, 0.0, 1.0);
+    }
+  }
+  return trails;
+}
@@ -21,6 +21,13 @@ fn rand_f32() -> f32 {
     return f32(rand_u32()) / f32(0xFFFFFFFFu);
 }
 
+// Returns a normally distributed f32 with mean 0 and standard deviation 1, using the Box-Muller
+// transform.
+fn rand_normal() -> f32 {
+    let r = sqrt(-2.0 * log(max(rand_f32(), 1e-7)));
+    return r * cos(6.2831853 * rand_f32());
+}
+
 fn hash(v: u32) -> u32 {
 	let state: u32 = v * 747796405u + 2891336453u;
 	let word: u32 = ((state >> ((state >> 28u) + 4u)) ^ state) * 277803737u;
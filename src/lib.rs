@@ -1,28 +1,94 @@
-#![feature(array_zip)]
-
 mod sim;
 
 pub use sim::*;
 
 use bevy::{
+    ecs::system::SystemParam,
     prelude::*,
     render::{
         extract_resource::{ExtractResource, ExtractResourcePlugin},
-        render_resource::{Extent3d, TextureDimension, TextureFormat, TextureUsages},
+        render_resource::{
+            Extent3d, TextureDimension, TextureFormat, TextureUsages, TextureViewDescriptor,
+            TextureViewDimension,
+        },
+        RenderApp,
     },
-    window::WindowResized,
+    window::{PrimaryWindow, WindowResized},
 };
 
-const SIZE: UVec2 = UVec2::new(1536, 1536);
+/// Size of the trail map in texels, which can change at runtime.
+///
+/// Agent positions (and [SpawnPattern::Burst] centers) are fractions of the world's width and
+/// height, so they stay put when the resolution changes. Distances, such as speeds, view distances
+/// and spawn radii, are measured in units of the world's height, so agents behave the same in
+/// every direction regardless of the aspect ratio.
+#[derive(Resource, Clone, Copy, Debug, PartialEq, Eq, Deref, ExtractResource)]
+pub struct Resolution(pub UVec2);
+
+impl Default for Resolution {
+    fn default() -> Self {
+        Self(UVec2::new(1536, 1536))
+    }
+}
+
+impl Resolution {
+    /// The width of the world in units of its height.
+    pub fn aspect_ratio(self) -> f32 {
+        self.x as f32 / self.y as f32
+    }
+
+    fn extent(self, depth_or_array_layers: u32) -> Extent3d {
+        Extent3d {
+            width: self.x,
+            height: self.y,
+            depth_or_array_layers,
+        }
+    }
+}
 
 #[derive(Resource, Clone, Deref, DerefMut, ExtractResource)]
 
-/// Represents the two alternating framebuffer.
+/// Represents the two alternating framebuffer. Each is an array texture holding the chemical trail
+/// of every species in its own channel (see [trail::LAYERS]).
 pub struct Framebuffers([Handle<Image>; 2]);
 
+#[derive(Resource, Clone, Deref, DerefMut, ExtractResource)]
+/// The image the chemical trails are colorized into for display.
+pub struct DisplayImage(Handle<Image>);
+
 #[derive(Component)]
 struct FillScreen;
 
+/// Maps the cursor to the world, through the camera and the sprite the display image is drawn on
+/// (which [stretch_to_screen] resizes to fill the window).
+#[derive(SystemParam)]
+pub struct WorldCursor<'w, 's> {
+    windows: Query<'w, 's, &'static Window, With<PrimaryWindow>>,
+    cameras: Query<'w, 's, (&'static Camera, &'static GlobalTransform)>,
+    sprites: Query<'w, 's, (&'static Sprite, &'static GlobalTransform), With<FillScreen>>,
+}
+
+impl WorldCursor<'_, '_> {
+    /// Returns the position under the cursor as a fraction of the world's width and height, like
+    /// agent positions, or `None` if the cursor isn't over the world.
+    pub fn position(&self) -> Option<Vec2> {
+        let cursor = self.windows.get_single().ok()?.cursor_position()?;
+        let (camera, camera_transform) =
+            self.cameras.iter().find(|(camera, _)| camera.is_active)?;
+        let point = camera.viewport_to_world_2d(camera_transform, cursor)?;
+        let (sprite, sprite_transform) = self.sprites.get_single().ok()?;
+        let size = sprite.custom_size?;
+        let local = sprite_transform
+            .affine()
+            .inverse()
+            .transform_point3(point.extend(0.0))
+            .truncate();
+        // the sprite is centered on its transform, and the first row of the image is at its top
+        let position = Vec2::new(local.x / size.x + 0.5, 0.5 - local.y / size.y);
+        (position.cmpge(Vec2::ZERO).all() && position.cmple(Vec2::ONE).all()).then_some(position)
+    }
+}
+
 fn stretch_to_screen(
     resize_event: Res<Events<WindowResized>>,
     mut query: Query<&mut Sprite, With<FillScreen>>,
@@ -38,26 +104,46 @@ fn stretch_to_screen(
     }
 }
 
-fn setup(mut commands: Commands, mut images: ResMut<Assets<Image>>) {
-    let images = [0, 1].map(|_| {
+fn setup(
+    mut commands: Commands,
+    mut images: ResMut<Assets<Image>>,
+    trail_format: Res<TrailFormat>,
+    resolution: Res<Resolution>,
+) {
+    let format = trail_format.texture_format();
+    let framebuffers = [0, 1].map(|_| {
         let mut image = Image::new_fill(
-            Extent3d {
-                width: SIZE.x,
-                height: SIZE.y,
-                depth_or_array_layers: 1,
-            },
+            resolution.extent(trail::LAYERS as u32),
             TextureDimension::D2,
-            &[0, 0, 0, 255],
-            TextureFormat::Rgba8Unorm,
+            // zero is empty in every format
+            &vec![0; format.describe().block_size as usize],
+            format,
         );
+        // the brush paints into the secondary framebuffer and copies the result back
         image.texture_descriptor.usage = TextureUsages::STORAGE_BINDING
             | TextureUsages::TEXTURE_BINDING
-            | TextureUsages::RENDER_ATTACHMENT;
+            | TextureUsages::RENDER_ATTACHMENT
+            | TextureUsages::COPY_SRC
+            | TextureUsages::COPY_DST;
+        image.texture_view_descriptor = Some(TextureViewDescriptor {
+            dimension: Some(TextureViewDimension::D2Array),
+            ..default()
+        });
 
         images.add(image)
     });
-    commands.insert_resource(Framebuffers(images.clone()));
-    let [primary, _] = images;
+    commands.insert_resource(Framebuffers(framebuffers));
+
+    let mut display = Image::new_fill(
+        resolution.extent(1),
+        TextureDimension::D2,
+        &[0, 0, 0, 255],
+        TextureFormat::Rgba8Unorm,
+    );
+    display.texture_descriptor.usage =
+        TextureUsages::TEXTURE_BINDING | TextureUsages::RENDER_ATTACHMENT;
+    let display = images.add(display);
+    commands.insert_resource(DisplayImage(display.clone()));
 
     // spawn a sprite for each image
     commands.spawn((
@@ -66,20 +152,61 @@ fn setup(mut commands: Commands, mut images: ResMut<Assets<Image>>) {
                 custom_size: Some(Vec2::new(1024.0, 1024.0)),
                 ..default()
             },
-            texture: primary,
+            texture: display,
             ..default()
         },
         FillScreen,
     ));
 }
 
-pub struct Plugin;
+/// Recreates the framebuffers and the display image when the [Resolution] changes. Trails are
+/// cleared, but agents keep their relative positions.
+fn resize_images(
+    resolution: Res<Resolution>,
+    framebuffers: Res<Framebuffers>,
+    display: Res<DisplayImage>,
+    mut images: ResMut<Assets<Image>>,
+) {
+    if !resolution.is_changed() {
+        return;
+    }
+    let layers = framebuffers
+        .iter()
+        .map(|handle| (handle, trail::LAYERS as u32))
+        .chain([(&display.0, 1)]);
+    for (handle, depth_or_array_layers) in layers {
+        let size = resolution.extent(depth_or_array_layers);
+        let image = images.get_mut(handle).unwrap();
+        if image.texture_descriptor.size != size {
+            image.resize(size);
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct Plugin {
+    /// Storage format of the trail map.
+    pub trail_format: TrailFormat,
+    /// Initial size of the trail map. Change the [Resolution] resource to resize it at runtime.
+    pub resolution: Resolution,
+    /// Whether to start with [HotReload] enabled.
+    pub hot_reload: bool,
+}
 
 impl bevy::app::Plugin for Plugin {
     fn build(&self, app: &mut App) {
-        app.add_plugin(ExtractResourcePlugin::<Framebuffers>::default())
+        // needed by the render world as soon as the simulation plugins are built
+        app.sub_app_mut(RenderApp)
+            .insert_resource(self.trail_format);
+        app.insert_resource(self.trail_format)
+            .insert_resource(self.resolution)
+            .insert_resource(HotReload(self.hot_reload))
+            .add_plugin(ExtractResourcePlugin::<Resolution>::default())
+            .add_plugin(ExtractResourcePlugin::<Framebuffers>::default())
+            .add_plugin(ExtractResourcePlugin::<DisplayImage>::default())
             .add_plugin(sim::Plugin)
             .add_startup_system(setup)
+            .add_system(resize_images)
             .add_system(stretch_to_screen.in_base_set(CoreSet::PreUpdate));
     }
 }
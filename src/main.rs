@@ -1,10 +1,8 @@
-use std::{
-    f32::consts::PI,
-    ops::{Add, RangeInclusive},
-};
+use std::{f32::consts::PI, ops::RangeInclusive};
 
 use bevy::{
     diagnostic::{Diagnostics, FrameTimeDiagnosticsPlugin},
+    ecs::system::SystemParam,
     prelude::*,
     window::PresentMode,
     winit::WinitSettings,
@@ -14,21 +12,110 @@ use bevy_egui::{
     EguiContexts, EguiPlugin, EguiSet,
 };
 use slime::{
-    species::{NumAgents, Qualities},
-    Options,
+    species::{
+        AgentLimit, Inertia, Interactions, MovementModel, NumAgents, Qualities, Reinitialize,
+        SpawnPattern, SteeringMode,
+    },
+    Behavior, BehaviorModule, Behaviors, BoundaryMode, BrushMode, BrushStroke, BrushStrokes,
+    FoodSource, FoodSourceBundle, Hook, Lifecycle, LiveAgents, Options, PipelineError, Resolution,
+    SimulationSeed, SimulationStep, Timestep, WorldCursor,
 };
 
-const EVAPORATION_DELTA: f32 = 1e-4;
-const SPEED_DELTA: f32 = 1e-7;
-const TURN_SPEED_DELTA: f32 = 1e-5;
-const VIEW_DISTANCE_DELTA: f32 = 1e-4;
-const FIELD_OF_VIEW_DELTA: f32 = 1e-3;
-const MAX_AGENTS_PER_SPECIES: u32 = 50_000;
+const EVAPORATION_DELTA: f32 = 6e-3;
+const DIFFUSION_DELTA: f32 = 1e-2;
+const KERNEL_SIGMA_DELTA: f32 = 1e-2;
+const SPEED_DELTA: f32 = 6e-6;
+const SENSOR_OFFSET_DELTA: f32 = 1e-4;
+const MAX_SENSOR_COUNT: u32 = 9;
+const MAX_SENSOR_SIZE: u32 = 7;
+const ROTATION_ANGLE_DELTA: f32 = 6e-4;
+const TEMPERATURE_DELTA: f32 = 1e-2;
+const DEFAULT_TEMPERATURE: f32 = 0.5;
+const HEADING_NOISE_DELTA: f32 = 1e-3;
+const INERTIA_DELTA: f32 = 1e-2;
+const ENERGY_DELTA: f32 = 1e-2;
+const MOVEMENT_PARAMETER_DELTA: f32 = 1e-3;
+const MOVEMENT_MODELS: [(&str, MovementModel); 4] = [
+    ("Constant", MovementModel::Constant),
+    (
+        "Lévy Walk",
+        MovementModel::Levy {
+            exponent: 2.0,
+            min_run: 0.01,
+        },
+    ),
+    (
+        "Run and Tumble",
+        MovementModel::RunAndTumble {
+            tumble_rate: 1.0,
+            sensitivity: 0.01,
+        },
+    ),
+    (
+        "Correlated Random Walk",
+        MovementModel::CorrelatedRandomWalk {
+            persistence_length: 0.1,
+        },
+    ),
+];
+const DEPOSIT_DELTA: f32 = 0.5;
+const TIMESTEP_DELTA: f32 = 1e-4;
+const SIMULATION_SPEED_DELTA: f32 = 1e-2;
+const MAX_SUBSTEPS: u32 = 32;
+const INTERACTION_DELTA: f32 = 1e-2;
+const MAX_AGENTS_PER_SPECIES: u32 = 4_000_000;
+const FOOD_POSITION_DELTA: f32 = 1e-3;
+const FOOD_RADIUS_DELTA: f32 = 1e-4;
+const FOOD_STRENGTH_DELTA: f32 = 1e-2;
+const BRUSH_RADIUS_DELTA: f32 = 1e-4;
+const BRUSH_STRENGTH_DELTA: f32 = 1e-1;
+const RESOLUTIONS: [(u32, u32); 5] = [
+    (1024, 1024),
+    (1536, 1536),
+    (1920, 1080),
+    (2560, 1440),
+    (3840, 2160),
+];
+const SPAWN_PATTERNS: [(&str, SpawnPattern); 7] = [
+    ("Disc", SpawnPattern::Disc),
+    ("Uniform", SpawnPattern::Uniform),
+    (
+        "Burst",
+        SpawnPattern::Burst {
+            center: Vec2::splat(0.5),
+        },
+    ),
+    ("Ring (outward)", SpawnPattern::RingOutward { radius: 0.4 }),
+    ("Ring (inward)", SpawnPattern::RingInward { radius: 0.4 }),
+    ("Random Heading", SpawnPattern::RandomHeading),
+    ("Grid", SpawnPattern::Grid),
+];
+/// Agents speed up on trails they are attracted to.
+const TRAIL_SURFING: &str = "
+fn move_agent(agent: Agent) -> Agent {
+  var moved = agent;
+  let tc = world_to_tex(vec2<u32>(textureDimensions(t_trails)), agent.pos);
+  let boost = 1.0 + clamp(sense(tc), 0.0, 2.0);
+  let heading = vec2<f32>(cos(agent.angle), sin(agent.angle));
+  moved.pos += boost * species.speed * options.timestep * heading / options.world_size;
+  return moved;
+}
+";
+
+fn setup(mut commands: Commands, mut behaviors: ResMut<Behaviors>) {
+    behaviors.insert(
+        "Trail Surfing",
+        BehaviorModule {
+            source: TRAIL_SURFING.to_owned(),
+            hooks: vec![Hook::Move],
+        },
+    );
 
-fn setup(mut commands: Commands) {
     commands.insert_resource(slime::Options {
-        evaporation: 0.003,
-        // diffusion: 1.0,
+        evaporation: 0.18,
+        boundary: BoundaryMode::Reflect,
+        diffusion: 1.0,
+        ..default()
     });
 
     commands.spawn((
@@ -39,6 +126,8 @@ fn setup(mut commands: Commands) {
                 color: Color::RED,
                 ..default()
             },
+            spawn_pattern: default(),
+            interactions: default(),
         },
     ));
 
@@ -62,18 +151,43 @@ impl Default for UiState {
     }
 }
 
+/// What the render world reports back to the UI.
+#[derive(SystemParam)]
+struct RenderReports<'w> {
+    pipeline_error: Res<'w, PipelineError>,
+    agent_limit: Res<'w, AgentLimit>,
+}
+
+#[allow(clippy::too_many_arguments)]
 fn draw_ui(
     mut commands: Commands,
     mut contexts: EguiContexts,
     mut ui_state: ResMut<UiState>,
     diagnostics: Res<Diagnostics>,
     mut options: ResMut<Options>,
+    mut seed: ResMut<SimulationSeed>,
+    mut resolution: ResMut<Resolution>,
+    mut timestep: ResMut<Timestep>,
+    step: Res<SimulationStep>,
+    reports: RenderReports,
     species_query: Query<(Entity, &Name, &mut NumAgents, &mut Qualities)>,
+    spawn_query: Query<&SpawnPattern>,
+    behaviors: Res<Behaviors>,
+    behavior_query: Query<Option<&Behavior>>,
+    interactions_query: Query<&Interactions>,
+    live_agents: Res<LiveAgents>,
 ) {
     let fps = diagnostics
         .get_measurement(FrameTimeDiagnosticsPlugin::FPS)
         .map(|x| x.value)
         .unwrap_or(0.);
+    if let Some(error) = reports.pipeline_error.get() {
+        egui::Window::new("Shader Error").show(contexts.ctx_mut(), |ui| {
+            egui::ScrollArea::vertical().show(ui, |ui| {
+                ui.label(egui::RichText::new(error).monospace());
+            });
+        });
+    }
     egui::Window::new("Slime Options")
         .default_open(false)
         .show(contexts.ctx_mut(), |ui| {
@@ -81,22 +195,107 @@ fn draw_ui(
                 ui.heading("Statistics");
 
                 ui.label(format!("FPS: {}", fps.round()));
+                ui.label(format!("Step: {}", step.get()));
                 let count = species_query.iter().count();
                 ui.label(format!("Species: {}", count));
+                // species with a lifecycle count their live agents
                 let agents = species_query
                     .iter()
-                    .map(|(_, _, count, _)| **count)
-                    .fold(0, Add::add);
+                    .map(|(id, _, count, qualities)| {
+                        qualities
+                            .lifecycle
+                            .and_then(|_| live_agents.get(id))
+                            .unwrap_or(**count)
+                    })
+                    .sum::<u32>();
                 ui.label(format!("Agents: {}", agents));
+                if let Some(limit) = reports.agent_limit.get() {
+                    ui.colored_label(
+                        egui::Color32::YELLOW,
+                        format!("Clamped to {} agents, the most the GPU can hold", limit),
+                    );
+                }
             }
             ui.separator();
 
             ui.heading("Simulation");
             ui.checkbox(&mut ui_state.vsync, "VSync");
 
+            ui.horizontal(|ui| {
+                let mut value = **seed;
+                if ui.add(egui::DragValue::new(&mut value)).changed() {
+                    **seed = value;
+                }
+                ui.label("Seed");
+            });
+
+            let Timestep {
+                mut step,
+                mut max_substeps,
+                mut speed,
+            } = timestep.clone();
+            let mut timestep_changed = false;
+            timestep_changed |= ui
+                .horizontal(|ui| {
+                    let ret = ui
+                        .add(egui::DragValue::new(&mut step).speed(TIMESTEP_DELTA))
+                        .changed();
+                    ui.label("Timestep (s)");
+                    ret
+                })
+                .inner;
+
+            timestep_changed |= ui
+                .horizontal(|ui| {
+                    let ret = ui
+                        .add(egui::Slider::new(
+                            &mut max_substeps,
+                            RangeInclusive::new(1, MAX_SUBSTEPS),
+                        ))
+                        .changed();
+                    ui.label("Max Steps per Frame");
+                    ret
+                })
+                .inner;
+
+            timestep_changed |= ui
+                .horizontal(|ui| {
+                    let ret = ui
+                        .add(egui::DragValue::new(&mut speed).speed(SIMULATION_SPEED_DELTA))
+                        .changed();
+                    ui.label("Simulation Speed");
+                    ret
+                })
+                .inner;
+
+            if timestep_changed {
+                *timestep = Timestep {
+                    step: step.max(1e-4),
+                    max_substeps,
+                    speed: speed.max(0.0),
+                };
+            }
+
+            egui::ComboBox::from_label("Resolution")
+                .selected_text(format!("{}x{}", resolution.x, resolution.y))
+                .show_ui(ui, |ui| {
+                    for (width, height) in RESOLUTIONS {
+                        let size = UVec2::new(width, height);
+                        if ui
+                            .selectable_label(**resolution == size, format!("{}x{}", width, height))
+                            .clicked()
+                        {
+                            *resolution = Resolution(size);
+                        }
+                    }
+                });
+
             let Options {
                 mut evaporation,
-                // mut diffusion,
+                mut boundary,
+                mut diffusion,
+                mut kernel_radius,
+                mut kernel_sigma,
             } = options.clone();
             let mut options_changed = false;
             options_changed |= ui
@@ -104,25 +303,65 @@ fn draw_ui(
                     let ret = ui
                         .add(egui::DragValue::new(&mut evaporation).speed(EVAPORATION_DELTA))
                         .changed();
-                    ui.label("Evaporation");
+                    ui.label("Evaporation (per s)");
                     ret
                 })
                 .inner;
 
-            // options_changed |= ui
-            //     .horizontal(|ui| {
-            //         let ret = ui
-            //             .add(egui::DragValue::new(&mut diffusion).speed(DIFFUSION_DELTA))
-            //             .changed();
-            //         ui.label("Diffusion");
-            //         ret
-            //     })
-            //     .inner;
+            options_changed |= egui::ComboBox::from_label("Boundary")
+                .selected_text(format!("{:?}", boundary))
+                .show_ui(ui, |ui| {
+                    let mut changed = false;
+                    for mode in BoundaryMode::ALL {
+                        changed |= ui
+                            .selectable_value(&mut boundary, mode, format!("{:?}", mode))
+                            .changed();
+                    }
+                    changed
+                })
+                .inner
+                .unwrap_or(false);
+
+            options_changed |= ui
+                .horizontal(|ui| {
+                    let ret = ui
+                        .add(egui::DragValue::new(&mut diffusion).speed(DIFFUSION_DELTA))
+                        .changed();
+                    ui.label("Diffusion");
+                    ret
+                })
+                .inner;
+
+            options_changed |= ui
+                .horizontal(|ui| {
+                    let ret = ui
+                        .add(egui::Slider::new(
+                            &mut kernel_radius,
+                            RangeInclusive::new(0, slime::MAX_KERNEL_RADIUS),
+                        ))
+                        .changed();
+                    ui.label("Blur Radius (texels)");
+                    ret
+                })
+                .inner;
+
+            options_changed |= ui
+                .horizontal(|ui| {
+                    let ret = ui
+                        .add(egui::DragValue::new(&mut kernel_sigma).speed(KERNEL_SIGMA_DELTA))
+                        .changed();
+                    ui.label("Blur Sigma (texels)");
+                    ret
+                })
+                .inner;
 
             if options_changed {
                 *options = Options {
-                    evaporation: evaporation.clamp(0.0, 1.0),
-                    // diffusion: diffusion.clamp(0.0, 1.0),
+                    evaporation: evaporation.max(0.0),
+                    boundary,
+                    diffusion: diffusion.clamp(0.0, 1.0),
+                    kernel_radius,
+                    kernel_sigma: kernel_sigma.max(0.1),
                 };
             }
 
@@ -146,6 +385,8 @@ fn draw_ui(
                     slime::SpeciesBundle {
                         num_agents: NumAgents::from(50000),
                         qualities: Qualities::default(),
+                        spawn_pattern: SpawnPattern::default(),
+                        interactions: Interactions::default(),
                     },
                 ));
                 ent.log_components();
@@ -212,24 +453,42 @@ fn draw_ui(
                 let Qualities {
                     mut color,
                     mut speed,
-                    mut turn_speed,
-                    mut view_distance,
-                    mut field_of_view,
+                    mut sensor_angle,
+                    mut sensor_offset,
+                    mut sensor_count,
+                    mut sensor_size,
+                    mut rotation_angle,
+                    mut steering,
+                    mut heading_noise,
+                    mut movement,
+                    mut inertia,
+                    mut lifecycle,
+                    mut deposit,
                 } = species_query
                     .get_component::<Qualities>(id)
                     .unwrap()
                     .clone();
 
-                let mut num_agents_changed = ui.button("Randomize Agents").clicked();
-                num_agents_changed |= ui
+                if ui.button("Reinitialize Agents").clicked() {
+                    commands.entity(id).insert(Reinitialize);
+                }
+                let num_agents_changed = ui
                     .horizontal(|ui| {
                         let ret = ui
-                            .add(egui::Slider::new(
-                                &mut num_agents,
-                                RangeInclusive::new(1, MAX_AGENTS_PER_SPECIES),
-                            ))
+                            .add(
+                                egui::Slider::new(
+                                    &mut num_agents,
+                                    RangeInclusive::new(1, MAX_AGENTS_PER_SPECIES),
+                                )
+                                .logarithmic(true),
+                            )
                             .changed();
-                        ui.label("Number of Agents");
+                        // species with a lifecycle only start out with this many
+                        ui.label(if lifecycle.is_some() {
+                            "Initial Agents"
+                        } else {
+                            "Number of Agents"
+                        });
                         ret
                     })
                     .inner;
@@ -237,6 +496,47 @@ fn draw_ui(
                 if num_agents_changed {
                     commands.entity(id).insert(NumAgents(num_agents));
                 }
+                if let Some(live) = live_agents.get(id).filter(|_| lifecycle.is_some()) {
+                    ui.label(format!("Live Agents: {}", live));
+                }
+
+                let spawn_pattern = spawn_query.get(id).unwrap();
+                egui::ComboBox::from_label("Spawn Pattern")
+                    .selected_text(
+                        SPAWN_PATTERNS
+                            .iter()
+                            .find(|(_, pattern)| pattern == spawn_pattern)
+                            .map_or("Custom", |(name, _)| name),
+                    )
+                    .show_ui(ui, |ui| {
+                        for (name, pattern) in SPAWN_PATTERNS {
+                            if ui
+                                .selectable_label(*spawn_pattern == pattern, name)
+                                .clicked()
+                            {
+                                commands.entity(id).insert(pattern);
+                            }
+                        }
+                    });
+
+                let behavior = behavior_query.get(id).unwrap().and_then(|b| b.as_deref());
+                egui::ComboBox::from_label("Behavior")
+                    .selected_text(behavior.unwrap_or("Built-in"))
+                    .show_ui(ui, |ui| {
+                        if ui
+                            .selectable_label(behavior.is_none(), "Built-in")
+                            .clicked()
+                        {
+                            commands.entity(id).insert(Behavior(None));
+                        }
+                        let mut names: Vec<_> = behaviors.names().collect();
+                        names.sort_unstable();
+                        for name in names {
+                            if ui.selectable_label(behavior == Some(name), name).clicked() {
+                                commands.entity(id).insert(Behavior(Some(name.to_owned())));
+                            }
+                        }
+                    });
 
                 let mut qualities_changed = false;
                 qualities_changed |= ui
@@ -244,7 +544,7 @@ fn draw_ui(
                         let mut color_flat = color.as_rgba_f32()[0..3].try_into().unwrap();
                         let changed = ui.color_edit_button_rgb(&mut color_flat).changed();
                         color = Color::rgb(color_flat[0], color_flat[1], color_flat[2]);
-                        ui.label("Trail Color");
+                        ui.label("Display Color");
                         changed
                     })
                     .inner;
@@ -254,7 +554,39 @@ fn draw_ui(
                         let changed = ui
                             .add(egui::DragValue::new(&mut speed).speed(SPEED_DELTA))
                             .changed();
-                        ui.label("Speed (units/frame)");
+                        ui.label("Speed (units/s)");
+                        changed
+                    })
+                    .inner;
+
+                qualities_changed |= ui
+                    .horizontal(|ui| {
+                        let changed = ui
+                            .add(egui::Slider::new(&mut sensor_angle, 0.0..=PI))
+                            .changed();
+                        ui.label("Sensor Angle (rad)");
+                        changed
+                    })
+                    .inner;
+
+                qualities_changed |= ui
+                    .horizontal(|ui| {
+                        let changed = ui
+                            .add(
+                                egui::DragValue::new(&mut sensor_offset).speed(SENSOR_OFFSET_DELTA),
+                            )
+                            .changed();
+                        ui.label("Sensor Offset (units)");
+                        changed
+                    })
+                    .inner;
+
+                qualities_changed |= ui
+                    .horizontal(|ui| {
+                        let changed = ui
+                            .add(egui::Slider::new(&mut sensor_count, 1..=MAX_SENSOR_COUNT))
+                            .changed();
+                        ui.label("Sensors");
                         changed
                     })
                     .inner;
@@ -262,9 +594,9 @@ fn draw_ui(
                 qualities_changed |= ui
                     .horizontal(|ui| {
                         let changed = ui
-                            .add(egui::DragValue::new(&mut turn_speed).speed(TURN_SPEED_DELTA))
+                            .add(egui::Slider::new(&mut sensor_size, 1..=MAX_SENSOR_SIZE))
                             .changed();
-                        ui.label("Turn Speed (rad/frame)");
+                        ui.label("Sensor Size (texels)");
                         changed
                     })
                     .inner;
@@ -273,39 +605,448 @@ fn draw_ui(
                     .horizontal(|ui| {
                         let changed = ui
                             .add(
-                                egui::DragValue::new(&mut view_distance).speed(VIEW_DISTANCE_DELTA),
+                                egui::DragValue::new(&mut rotation_angle)
+                                    .speed(ROTATION_ANGLE_DELTA),
                             )
                             .changed();
-                        ui.label("View Distance (units)");
+                        ui.label("Rotation Angle (rad/s)");
                         changed
                     })
                     .inner;
 
+                egui::ComboBox::from_label("Steering")
+                    .selected_text(match steering {
+                        SteeringMode::Argmax => "Argmax",
+                        SteeringMode::Jones => "Jones",
+                        SteeringMode::WeightedRandom => "Weighted Random",
+                        SteeringMode::Softmax { .. } => "Softmax",
+                    })
+                    .show_ui(ui, |ui| {
+                        for (mode, name) in [
+                            (SteeringMode::Argmax, "Argmax"),
+                            (SteeringMode::Jones, "Jones"),
+                            (SteeringMode::WeightedRandom, "Weighted Random"),
+                        ] {
+                            qualities_changed |=
+                                ui.selectable_value(&mut steering, mode, name).changed();
+                        }
+                        if ui
+                            .selectable_label(
+                                matches!(steering, SteeringMode::Softmax { .. }),
+                                "Softmax",
+                            )
+                            .clicked()
+                            && !matches!(steering, SteeringMode::Softmax { .. })
+                        {
+                            steering = SteeringMode::Softmax {
+                                temperature: DEFAULT_TEMPERATURE,
+                            };
+                            qualities_changed = true;
+                        }
+                    });
+
+                if let SteeringMode::Softmax { temperature } = &mut steering {
+                    qualities_changed |= ui
+                        .horizontal(|ui| {
+                            let changed = ui
+                                .add(egui::DragValue::new(temperature).speed(TEMPERATURE_DELTA))
+                                .changed();
+                            ui.label("Temperature");
+                            changed
+                        })
+                        .inner;
+                    *temperature = temperature.max(0.0);
+                }
+
                 qualities_changed |= ui
                     .horizontal(|ui| {
                         let changed = ui
                             .add(
-                                egui::DragValue::new(&mut field_of_view).speed(FIELD_OF_VIEW_DELTA),
+                                egui::DragValue::new(&mut heading_noise).speed(HEADING_NOISE_DELTA),
                             )
                             .changed();
-                        ui.label("Field of View (rad)");
+                        ui.label("Heading Noise (rad/√s)");
+                        changed
+                    })
+                    .inner;
+
+                egui::ComboBox::from_label("Movement")
+                    .selected_text(
+                        MOVEMENT_MODELS
+                            .iter()
+                            .find(|(_, model)| {
+                                std::mem::discriminant(model) == std::mem::discriminant(&movement)
+                            })
+                            .map_or("Custom", |(name, _)| name),
+                    )
+                    .show_ui(ui, |ui| {
+                        for (name, model) in MOVEMENT_MODELS {
+                            let selected =
+                                std::mem::discriminant(&model) == std::mem::discriminant(&movement);
+                            if ui.selectable_label(selected, name).clicked() && !selected {
+                                movement = model;
+                                qualities_changed = true;
+                            }
+                        }
+                    });
+
+                let mut movement_parameter = |ui: &mut egui::Ui, value: &mut f32, label: &str| {
+                    qualities_changed |= ui
+                        .horizontal(|ui| {
+                            let changed = ui
+                                .add(egui::DragValue::new(value).speed(MOVEMENT_PARAMETER_DELTA))
+                                .changed();
+                            ui.label(label);
+                            changed
+                        })
+                        .inner;
+                    *value = value.max(0.0);
+                };
+                match &mut movement {
+                    MovementModel::Constant => {}
+                    MovementModel::Levy { exponent, min_run } => {
+                        movement_parameter(ui, exponent, "Lévy Exponent");
+                        *exponent = exponent.clamp(1.0, 3.0);
+                        movement_parameter(ui, min_run, "Minimum Run (units)");
+                    }
+                    MovementModel::RunAndTumble {
+                        tumble_rate,
+                        sensitivity,
+                    } => {
+                        movement_parameter(ui, tumble_rate, "Tumble Rate (per s)");
+                        movement_parameter(ui, sensitivity, "Gradient Sensitivity");
+                    }
+                    MovementModel::CorrelatedRandomWalk { persistence_length } => {
+                        movement_parameter(ui, persistence_length, "Persistence Length (units)");
+                    }
+                }
+
+                let mut has_inertia = inertia.is_some();
+                if ui.checkbox(&mut has_inertia, "Inertia").changed() {
+                    inertia = has_inertia.then(Inertia::default);
+                    qualities_changed = true;
+                }
+                if let Some(Inertia {
+                    mass,
+                    drag,
+                    trail_drag,
+                    max_speed,
+                }) = &mut inertia
+                {
+                    for (value, speed, label) in [
+                        (mass, INERTIA_DELTA, "Mass"),
+                        (drag, INERTIA_DELTA, "Drag (per s)"),
+                        (
+                            trail_drag,
+                            INERTIA_DELTA,
+                            "Trail Drag (per s per unit of trail)",
+                        ),
+                        (max_speed, SPEED_DELTA, "Max Speed (units/s)"),
+                    ] {
+                        qualities_changed |= ui
+                            .horizontal(|ui| {
+                                let changed =
+                                    ui.add(egui::DragValue::new(value).speed(speed)).changed();
+                                ui.label(label);
+                                changed
+                            })
+                            .inner;
+                        *value = value.max(0.0);
+                    }
+                }
+
+                qualities_changed |= ui
+                    .horizontal(|ui| {
+                        let changed = ui
+                            .add(egui::DragValue::new(&mut deposit).speed(DEPOSIT_DELTA))
+                            .changed();
+                        ui.label("Deposit (per agent/s)");
                         changed
                     })
                     .inner;
 
+                let mut has_lifecycle = lifecycle.is_some();
+                if ui.checkbox(&mut has_lifecycle, "Lifecycle").changed() {
+                    lifecycle = has_lifecycle.then(Lifecycle::default);
+                    qualities_changed = true;
+                }
+                if let Some(Lifecycle {
+                    capacity,
+                    initial_energy,
+                    drain,
+                    intake,
+                    split_energy,
+                }) = &mut lifecycle
+                {
+                    qualities_changed |= ui
+                        .horizontal(|ui| {
+                            let changed = ui
+                                .add(
+                                    egui::Slider::new(capacity, 1..=MAX_AGENTS_PER_SPECIES)
+                                        .logarithmic(true),
+                                )
+                                .changed();
+                            ui.label("Capacity");
+                            changed
+                        })
+                        .inner;
+                    for (value, label) in [
+                        (initial_energy, "Initial Energy"),
+                        (drain, "Drain (per s)"),
+                        (intake, "Intake (per s per unit of food)"),
+                        (split_energy, "Split Energy"),
+                    ] {
+                        qualities_changed |= ui
+                            .horizontal(|ui| {
+                                let changed = ui
+                                    .add(egui::DragValue::new(value).speed(ENERGY_DELTA))
+                                    .changed();
+                                ui.label(label);
+                                changed
+                            })
+                            .inner;
+                        *value = value.max(0.0);
+                    }
+                }
+
                 if qualities_changed {
                     commands.entity(id).insert(Qualities {
                         color,
                         speed: speed.max(0.0),
-                        turn_speed: turn_speed.max(0.0),
-                        view_distance: view_distance.max(0.0).min(1.0),
-                        field_of_view: field_of_view.max(0.0).min(2.0 * PI),
+                        sensor_angle,
+                        sensor_offset: sensor_offset.clamp(0.0, 1.0),
+                        sensor_count,
+                        sensor_size,
+                        rotation_angle: rotation_angle.max(0.0),
+                        steering,
+                        heading_noise: heading_noise.max(0.0),
+                        movement,
+                        inertia,
+                        lifecycle,
+                        deposit: deposit.max(0.0),
                     });
                 }
+
+                ui.separator();
+                ui.heading("Interactions");
+
+                let mut interactions = interactions_query.get(id).unwrap().clone();
+                let mut interactions_changed = false;
+                for (other, name, _, _) in &species_query {
+                    let mut weight = interactions.weight(id, other);
+                    let changed = ui
+                        .horizontal(|ui| {
+                            let changed = ui
+                                .add(egui::DragValue::new(&mut weight).speed(INTERACTION_DELTA))
+                                .changed();
+                            ui.label(format!("[{:?}] {}", other, name));
+                            changed
+                        })
+                        .inner;
+                    if changed {
+                        if other == id {
+                            interactions.self_weight = weight;
+                        } else {
+                            interactions.weights.insert(other, weight);
+                        }
+                        interactions_changed = true;
+                    }
+                }
+
+                if interactions_changed {
+                    commands.entity(id).insert(interactions);
+                }
             }
         });
 }
 
+fn draw_food_ui(
+    mut commands: Commands,
+    mut contexts: EguiContexts,
+    mut query: Query<(Entity, &mut FoodSource, &mut Transform)>,
+) {
+    egui::Window::new("Food Sources")
+        .default_open(false)
+        .show(contexts.ctx_mut(), |ui| {
+            ui.label("Negative strengths repel agents.");
+            if ui
+                .add_enabled(
+                    query.iter().len() < slime::MAX_FOOD_SOURCES,
+                    egui::Button::new("Add Source"),
+                )
+                .clicked()
+            {
+                commands.spawn(FoodSourceBundle {
+                    transform: Transform::from_xyz(0.5, 0.5, 0.0),
+                    ..default()
+                });
+            }
+            for (id, mut source, mut transform) in &mut query {
+                ui.separator();
+                ui.horizontal(|ui| {
+                    let mut position = transform.translation.truncate();
+                    let changed = ui
+                        .add(
+                            egui::DragValue::new(&mut position.x)
+                                .speed(FOOD_POSITION_DELTA)
+                                .clamp_range(0.0..=1.0),
+                        )
+                        .changed()
+                        | ui.add(
+                            egui::DragValue::new(&mut position.y)
+                                .speed(FOOD_POSITION_DELTA)
+                                .clamp_range(0.0..=1.0),
+                        )
+                        .changed();
+                    if changed {
+                        transform.translation = position.extend(0.0);
+                    }
+                    ui.label("Position");
+                    if ui.button("Delete").clicked() {
+                        commands.entity(id).despawn();
+                    }
+                });
+                let FoodSource {
+                    mut radius,
+                    mut falloff,
+                    mut strength,
+                } = source.clone();
+                let mut changed = false;
+                ui.horizontal(|ui| {
+                    changed |= ui
+                        .add(egui::DragValue::new(&mut radius).speed(FOOD_RADIUS_DELTA))
+                        .changed();
+                    ui.label("Radius");
+                    changed |= ui
+                        .add(egui::DragValue::new(&mut falloff).speed(FOOD_RADIUS_DELTA))
+                        .changed();
+                    ui.label("Falloff");
+                    changed |= ui
+                        .add(egui::DragValue::new(&mut strength).speed(FOOD_STRENGTH_DELTA))
+                        .changed();
+                    ui.label("Strength");
+                });
+                if changed {
+                    *source = FoodSource {
+                        radius: radius.max(0.0),
+                        falloff: falloff.max(0.0),
+                        strength,
+                    };
+                }
+            }
+        });
+}
+
+#[derive(Resource)]
+struct BrushState {
+    enabled: bool,
+    mode: BrushMode,
+    /// Units (world heights).
+    radius: f32,
+    /// Chemical or nutrient per second, or the fraction erased per second.
+    strength: f32,
+}
+
+impl Default for BrushState {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            mode: BrushMode::Attract,
+            radius: 0.02,
+            strength: 10.0,
+        }
+    }
+}
+
+fn brush_mode_name(
+    mode: BrushMode,
+    species_query: &Query<(Entity, &Name), With<NumAgents>>,
+) -> String {
+    match mode {
+        BrushMode::Deposit(id) => species_query
+            .get(id)
+            .map_or("Deposit".to_owned(), |(_, name)| {
+                format!("Deposit ({})", name)
+            }),
+        BrushMode::Attract => "Attractant".to_owned(),
+        BrushMode::Repel => "Repellent".to_owned(),
+        BrushMode::Erase => "Erase".to_owned(),
+        BrushMode::Obstacle => "Obstacle".to_owned(),
+    }
+}
+
+fn draw_brush_ui(
+    mut contexts: EguiContexts,
+    mut brush: ResMut<BrushState>,
+    species_query: Query<(Entity, &Name), With<NumAgents>>,
+) {
+    egui::Window::new("Brush")
+        .default_open(false)
+        .show(contexts.ctx_mut(), |ui| {
+            ui.checkbox(&mut brush.enabled, "Paint with the left mouse button");
+            egui::ComboBox::from_label("Mode")
+                .selected_text(brush_mode_name(brush.mode, &species_query))
+                .show_ui(ui, |ui| {
+                    let modes = species_query
+                        .iter()
+                        .map(|(id, _)| BrushMode::Deposit(id))
+                        .chain([
+                            BrushMode::Attract,
+                            BrushMode::Repel,
+                            BrushMode::Erase,
+                            BrushMode::Obstacle,
+                        ]);
+                    for mode in modes {
+                        let name = brush_mode_name(mode, &species_query);
+                        ui.selectable_value(&mut brush.mode, mode, name);
+                    }
+                });
+            ui.horizontal(|ui| {
+                ui.add(
+                    egui::DragValue::new(&mut brush.radius)
+                        .speed(BRUSH_RADIUS_DELTA)
+                        .clamp_range(0.0..=1.0),
+                );
+                ui.label("Radius (units)");
+            });
+            ui.horizontal(|ui| {
+                ui.add(
+                    egui::DragValue::new(&mut brush.strength)
+                        .speed(BRUSH_STRENGTH_DELTA)
+                        .clamp_range(0.0..=f32::MAX),
+                );
+                ui.label("Strength (per s)");
+            });
+        });
+}
+
+fn paint(
+    mut contexts: EguiContexts,
+    brush: Res<BrushState>,
+    buttons: Res<Input<MouseButton>>,
+    time: Res<Time>,
+    cursor: WorldCursor,
+    mut strokes: ResMut<BrushStrokes>,
+) {
+    let ctx = contexts.ctx_mut();
+    if !brush.enabled
+        || !buttons.pressed(MouseButton::Left)
+        || ctx.is_pointer_over_area()
+        || ctx.is_using_pointer()
+    {
+        return;
+    }
+    let Some(position) = cursor.position() else {
+        return;
+    };
+    strokes.push(BrushStroke {
+        mode: brush.mode,
+        position,
+        radius: brush.radius,
+        strength: brush.strength * time.delta_seconds(),
+    });
+}
+
 fn configure_window(ui_state: Res<UiState>, mut windows: Query<&mut Window>) {
     let mut window = windows.single_mut();
     window.present_mode = if ui_state.vsync {
@@ -318,22 +1059,36 @@ fn configure_window(ui_state: Res<UiState>, mut windows: Query<&mut Window>) {
 fn main() {
     App::new()
         .init_resource::<UiState>()
+        .init_resource::<BrushState>()
         .insert_resource(WinitSettings::game())
         .insert_resource(ClearColor(Color::BLACK))
-        .add_plugins(DefaultPlugins.set(WindowPlugin {
-            primary_window: Some(Window {
-                title: "slime by Meyer Zinn".into(),
-                resolution: (640., 640.).into(),
-                present_mode: PresentMode::AutoVsync,
-                ..default()
-            }),
-            ..default()
-        }))
+        .add_plugins(
+            DefaultPlugins
+                .set(WindowPlugin {
+                    primary_window: Some(Window {
+                        title: "slime by Meyer Zinn".into(),
+                        resolution: (640., 640.).into(),
+                        present_mode: PresentMode::AutoVsync,
+                        ..default()
+                    }),
+                    ..default()
+                })
+                .set(AssetPlugin {
+                    watch_for_changes: cfg!(feature = "hot-reload"),
+                    ..default()
+                }),
+        )
         .add_plugin(FrameTimeDiagnosticsPlugin)
         .add_plugin(EguiPlugin)
-        .add_plugin(slime::Plugin)
+        .add_plugin(slime::Plugin {
+            hot_reload: cfg!(feature = "hot-reload"),
+            ..default()
+        })
         .add_startup_system(setup)
         .add_system(configure_window)
         .add_system(draw_ui.after(EguiSet::BeginFrame))
+        .add_system(draw_food_ui.after(EguiSet::BeginFrame))
+        .add_system(draw_brush_ui.after(EguiSet::BeginFrame))
+        .add_system(paint.after(EguiSet::BeginFrame))
         .run()
 }
@@ -0,0 +1,346 @@
+use bevy::{
+    prelude::*,
+    render::{
+        extract_resource::{ExtractResource, ExtractResourcePlugin},
+        render_resource::{
+            CachedComputePipelineId, CachedPipelineState, ComputePipeline, PipelineCache,
+            PipelineCacheError, ShaderDefVal,
+        },
+        Extract, RenderApp, RenderSet,
+    },
+    utils::HashMap,
+};
+
+use super::{
+    species::{self, Qualities, MAX_SPECIES},
+    BindGroupLayouts, PipelineError,
+};
+
+/// A function of simulate.wgsl that a [BehaviorModule] can replace. The module must define the
+/// function with the same signature.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Hook {
+    /// `fn sense(tc: vec2<u32>) -> f32` scores the trails in a texel of the trail map.
+    Sense,
+    /// `fn steer(agent: Agent) -> f32` returns the agent's new heading.
+    Steer,
+    /// `fn move_agent(agent: Agent) -> Agent` advances the agent along its heading (`move` is
+    /// reserved in WGSL). The boundary is applied afterwards.
+    Move,
+    /// `fn deposit(agent: Agent) -> f32` returns the trail strength the agent adds to the texel it
+    /// occupies per second.
+    Deposit,
+}
+
+impl Hook {
+    fn shader_def(self) -> ShaderDefVal {
+        match self {
+            Self::Sense => "BEHAVIOR_SENSE",
+            Self::Steer => "BEHAVIOR_STEER",
+            Self::Move => "BEHAVIOR_MOVE",
+            Self::Deposit => "BEHAVIOR_DEPOSIT",
+        }
+        .into()
+    }
+}
+
+/// WGSL source that replaces some of the [Hook]s of simulate.wgsl. It is compiled together with
+/// simulate.wgsl, so it can use anything the simulation shader defines.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct BehaviorModule {
+    pub source: String,
+    /// The hooks `source` defines.
+    pub hooks: Vec<Hook>,
+}
+
+/// The [BehaviorModule]s species can pick with [Behavior], by name. Inserting a module under an
+/// existing name replaces it and rebuilds the pipelines of the species following it.
+#[derive(Resource, Clone, Debug, Default)]
+pub struct Behaviors(HashMap<String, BehaviorModule>);
+
+impl Behaviors {
+    pub fn insert(&mut self, name: impl Into<String>, module: BehaviorModule) {
+        self.0.insert(name.into(), module);
+    }
+
+    pub fn remove(&mut self, name: &str) -> Option<BehaviorModule> {
+        self.0.remove(name)
+    }
+
+    /// Returns the names of the registered modules.
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.0.keys().map(String::as_str)
+    }
+}
+
+/// Selects the [BehaviorModule] a species' agents follow, by name. Species without one, or whose
+/// module isn't registered, follow the built-in behavior.
+#[derive(Component, Clone, Debug, Default, PartialEq, Eq, Deref, DerefMut)]
+pub struct Behavior(pub Option<String>);
+
+#[derive(Resource, Clone, Default, ExtractResource)]
+/// The shader of each [BehaviorModule], along with the module it was built from.
+pub(crate) struct BehaviorShaders(HashMap<String, (Handle<Shader>, BehaviorModule)>);
+
+fn load_behavior_shaders(
+    behaviors: Res<Behaviors>,
+    mut shaders: ResMut<Assets<Shader>>,
+    mut behavior_shaders: ResMut<BehaviorShaders>,
+) {
+    if !behaviors.is_changed() {
+        return;
+    }
+    behavior_shaders
+        .0
+        .retain(|name, _| behaviors.0.contains_key(name));
+    for (name, module) in &behaviors.0 {
+        if behavior_shaders
+            .0
+            .get(name)
+            .is_some_and(|(_, loaded)| loaded == module)
+        {
+            continue;
+        }
+        // the module's hooks take the place of the defaults in simulate.wgsl
+        let shader = Shader::from_wgsl(format!("#import \"{}\"\n{}", super::SHADER, module.source));
+        let handle = match behavior_shaders.0.get(name) {
+            // keep the handle, so that the pipeline cache recompiles the pipelines using it
+            Some((handle, _)) => shaders.set(handle, shader),
+            None => shaders.add(shader),
+        };
+        behavior_shaders
+            .0
+            .insert(name.clone(), (handle, module.clone()));
+    }
+}
+
+/// Selects the specialization of the update and project pipelines a species runs. Species with the
+/// default key run the built-in pipelines.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub(crate) struct PipelineKey {
+    /// The name of the species' [BehaviorModule], `None` for the built-in behavior.
+    pub(crate) behavior: Option<String>,
+    /// The shader def of the species' [SteeringMode](species::SteeringMode), if it isn't the
+    /// default.
+    pub(crate) steering: Option<&'static str>,
+    /// The shader def of the species' [MovementModel](species::MovementModel), if it isn't the
+    /// default.
+    pub(crate) movement: Option<&'static str>,
+    /// Whether the species has [Inertia](species::Inertia).
+    pub(crate) inertia: bool,
+    /// Whether the species has a [Lifecycle](super::Lifecycle).
+    pub(crate) lifecycle: bool,
+}
+
+impl std::fmt::Display for PipelineKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "behavior {}, steering {}, movement {}{}{}",
+            self.behavior.as_deref().unwrap_or("(built-in)"),
+            self.steering.unwrap_or("(argmax)"),
+            self.movement.unwrap_or("(constant)"),
+            if self.inertia { ", inertia" } else { "" },
+            if self.lifecycle { ", lifecycle" } else { "" }
+        )
+    }
+}
+
+#[derive(Resource, Default, Deref)]
+/// The [PipelineKey] each slot's species asks for. Lives in the Render world.
+pub(crate) struct RequestedPipelines([PipelineKey; MAX_SPECIES]);
+
+#[derive(Resource, Default, Deref)]
+/// The [PipelineKey] the species in each slot runs: the key it asks for once that key's pipelines
+/// are ready, and until then the key it ran before, so that species keep moving while their
+/// pipelines compile. Lives in the Render world.
+pub(crate) struct SlotPipelines([PipelineKey; MAX_SPECIES]);
+
+fn render_extract_slot_pipelines(
+    mut requested: ResMut<RequestedPipelines>,
+    slots: Res<species::Slots>,
+    // extracted from the main world, so missing until the first extraction finishes
+    shaders: Option<Res<BehaviorShaders>>,
+    query: Extract<Query<(Entity, &Qualities, Option<&Behavior>)>>,
+) {
+    let mut keys: [PipelineKey; MAX_SPECIES] = Default::default();
+    for (id, qualities, behavior) in &query {
+        let Some(slot) = slots.get(id) else {
+            continue;
+        };
+        keys[slot].steering = qualities.steering.shader_def();
+        keys[slot].movement = qualities.movement.shader_def();
+        keys[slot].inertia = qualities.inertia.is_some();
+        keys[slot].lifecycle = qualities.lifecycle.is_some();
+        if let (Some(name), Some(shaders)) = (behavior.and_then(|b| b.as_ref()), &shaders) {
+            if shaders.0.contains_key(name) {
+                keys[slot].behavior = Some(name.clone());
+            }
+        }
+    }
+    // only flag a change when a key actually changed
+    if requested.0 != keys {
+        requested.0 = keys;
+    }
+}
+
+/// The update and project pipelines of a [PipelineKey].
+struct Specialization {
+    hooks: Vec<Hook>,
+    update: CachedComputePipelineId,
+    project: CachedComputePipelineId,
+    /// The last compilation error reported for the pipelines.
+    error: Option<String>,
+    /// The last update and project pipelines that compiled, which keep running while the
+    /// pipelines recompile (or fail to).
+    ready: Option<(ComputePipeline, ComputePipeline)>,
+}
+
+#[derive(Resource, Default)]
+/// The pipelines specialized for each [PipelineKey] in use, which are queued the first time a
+/// species needs them. Lives in the Render world.
+pub(crate) struct SpecializedPipelines(HashMap<PipelineKey, Specialization>);
+
+impl SpecializedPipelines {
+    /// Returns the update and project pipelines of a key, once they have compiled.
+    pub(crate) fn get(&self, key: &PipelineKey) -> Option<(&ComputePipeline, &ComputePipeline)> {
+        let (update, project) = self.0.get(key)?.ready.as_ref()?;
+        Some((update, project))
+    }
+
+    /// Whether the species with a key can run, i.e. the key is the default one or its pipelines
+    /// have compiled.
+    fn ready(&self, key: &PipelineKey) -> bool {
+        *key == PipelineKey::default() || self.get(key).is_some()
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn render_queue_specialized_pipelines(
+    mut pipelines: ResMut<SpecializedPipelines>,
+    requested: Res<RequestedPipelines>,
+    mut slot_pipelines: ResMut<SlotPipelines>,
+    shaders: Res<BehaviorShaders>,
+    asset_server: Res<AssetServer>,
+    pipeline_cache: Res<PipelineCache>,
+    pipeline_error: Res<PipelineError>,
+    layouts: BindGroupLayouts,
+) {
+    let default_key = PipelineKey::default();
+    for key in requested.iter().filter(|key| **key != default_key) {
+        let (shader, hooks) = match &key.behavior {
+            Some(name) => {
+                let Some((shader, module)) = shaders.0.get(name) else {
+                    continue;
+                };
+                (shader.clone(), &module.hooks[..])
+            }
+            None => (asset_server.load(super::SHADER), &[][..]),
+        };
+        if pipelines
+            .0
+            .get(key)
+            .is_some_and(|pipelines| pipelines.hooks == hooks)
+        {
+            continue;
+        }
+        let mut shader_defs = super::shader_defs();
+        shader_defs.extend(hooks.iter().map(|hook| hook.shader_def()));
+        shader_defs.extend(key.steering.map(ShaderDefVal::from));
+        shader_defs.extend(key.movement.map(ShaderDefVal::from));
+        if key.inertia {
+            shader_defs.push("INERTIA".into());
+        }
+        if key.lifecycle {
+            shader_defs.push("LIFECYCLE".into());
+        }
+        let [update, project] = super::agent_pipeline_descriptors(&layouts, shader, shader_defs)
+            .map(|descriptor| pipeline_cache.queue_compute_pipeline(descriptor));
+        // the old pipelines run until the new ones are ready
+        let ready = pipelines
+            .0
+            .remove(key)
+            .and_then(|pipelines| pipelines.ready);
+        pipelines.0.insert(
+            key.clone(),
+            Specialization {
+                hooks: hooks.to_vec(),
+                update,
+                project,
+                error: None,
+                ready,
+            },
+        );
+    }
+
+    for (key, pipelines) in &mut pipelines.0 {
+        let error = [pipelines.update, pipelines.project]
+            .into_iter()
+            .find_map(|id| match pipeline_cache.get_compute_pipeline_state(id) {
+                // the pipeline cache retries these
+                CachedPipelineState::Err(
+                    PipelineCacheError::ShaderNotLoaded(_)
+                    | PipelineCacheError::ShaderImportNotYetAvailable,
+                ) => None,
+                CachedPipelineState::Err(error) => Some(format!("{}: {}", key, error)),
+                _ => None,
+            });
+        if error == pipelines.error {
+            continue;
+        }
+        match &error {
+            Some(error) => {
+                error!("failed to compile the simulation pipelines: {}", error);
+                pipeline_error.set(Some(error.clone()));
+            }
+            // the module was fixed, so clear its error unless another error replaced it
+            None if pipeline_error.get() == pipelines.error => pipeline_error.set(None),
+            None => {}
+        }
+        pipelines.error = error;
+    }
+
+    for pipelines in pipelines.0.values_mut() {
+        if let (Some(update), Some(project)) = (
+            pipeline_cache.get_compute_pipeline(pipelines.update),
+            pipeline_cache.get_compute_pipeline(pipelines.project),
+        ) {
+            pipelines.ready = Some((update.clone(), project.clone()));
+        }
+    }
+
+    // switch species over to the pipelines they ask for once those are ready
+    let keys = std::array::from_fn(|slot| {
+        [&requested[slot], &slot_pipelines[slot]]
+            .into_iter()
+            .find(|key| pipelines.ready(key))
+            .cloned()
+            .unwrap_or_default()
+    });
+    // only flag a change when a key actually changed
+    if slot_pipelines.0 != keys {
+        slot_pipelines.0 = keys;
+    }
+}
+
+pub(crate) struct Plugin;
+
+impl bevy::app::Plugin for Plugin {
+    fn build(&self, app: &mut App) {
+        app.add_plugin(ExtractResourcePlugin::<BehaviorShaders>::default())
+            .init_resource::<Behaviors>()
+            .init_resource::<BehaviorShaders>()
+            .add_system(load_behavior_shaders);
+
+        app.sub_app_mut(RenderApp)
+            .init_resource::<RequestedPipelines>()
+            .init_resource::<SlotPipelines>()
+            .init_resource::<SpecializedPipelines>()
+            .add_system(
+                render_extract_slot_pipelines
+                    .in_schedule(ExtractSchedule)
+                    .after(species::render_extract_slots),
+            )
+            .add_system(render_queue_specialized_pipelines.in_set(RenderSet::Queue));
+    }
+}
@@ -3,13 +3,77 @@ use bevy::{
     render::{
         render_resource::{
             BindGroup, BindGroupDescriptor, BindGroupEntry, BindGroupLayout,
-            BindGroupLayoutDescriptor, BindGroupLayoutEntry, BindingType, BufferBindingType,
-            BufferInitDescriptor, BufferUsages, ShaderStages,
+            BindGroupLayoutDescriptor, BindGroupLayoutEntry, BindingType, Buffer,
+            BufferBindingType, BufferDescriptor, BufferInitDescriptor, BufferUsages, ShaderStages,
         },
-        renderer::RenderDevice,
-        RenderApp,
+        renderer::{RenderDevice, RenderQueue},
+        RenderApp, RenderSet,
     },
 };
+use bytemuck::{Pod, Zeroable};
+
+use super::Options;
+
+/// The largest supported blur kernel radius, in texels.
+pub const MAX_KERNEL_RADIUS: u32 = 15;
+
+/// Weights of the kernel taps at offsets `0..=MAX_KERNEL_RADIUS`, packed into vec4s so that the
+/// array has the same layout in a uniform buffer.
+const KERNEL_WEIGHT_VECTORS: usize = (MAX_KERNEL_RADIUS as usize + 1) / 4;
+
+/// A symmetric 1D gaussian kernel, applied once along each axis.
+#[derive(Copy, Clone, Default, Pod, Zeroable)]
+#[repr(C)]
+struct GpuKernel {
+    radius: u32,
+    _padding: [u32; 3],
+    weights: [Vec4; KERNEL_WEIGHT_VECTORS],
+}
+
+impl GpuKernel {
+    /// Samples a gaussian with standard deviation `sigma`, normalized so that the taps at
+    /// `-radius..=radius` sum to one.
+    fn new(radius: u32, sigma: f32) -> Self {
+        let radius = radius.min(MAX_KERNEL_RADIUS);
+        let sigma = sigma.max(1e-3);
+        let mut weights = [0.0; MAX_KERNEL_RADIUS as usize + 1];
+        for (offset, weight) in weights.iter_mut().enumerate().take(radius as usize + 1) {
+            *weight = (-0.5 * (offset as f32 / sigma).powi(2)).exp();
+        }
+        // every tap but the center one is applied on both sides
+        let total = 2.0 * weights.iter().sum::<f32>() - weights[0];
+        let weights: Vec<_> = weights.iter().map(|weight| weight / total).collect();
+        Self {
+            radius,
+            _padding: [0; 3],
+            weights: std::array::from_fn(|i| Vec4::from_slice(&weights[i * 4..])),
+        }
+    }
+}
+
+#[derive(Resource, Deref)]
+/// The blur kernel, which is regenerated whenever the options change.
+struct KernelBuffer(Buffer);
+
+impl FromWorld for KernelBuffer {
+    fn from_world(world: &mut World) -> Self {
+        let device: &RenderDevice = world.resource::<RenderDevice>();
+        let buffer = device.create_buffer(&BufferDescriptor {
+            label: "blur::KernelBuffer".into(),
+            size: std::mem::size_of::<GpuKernel>() as u64,
+            usage: BufferUsages::COPY_DST | BufferUsages::UNIFORM,
+            mapped_at_creation: false,
+        });
+        Self(buffer)
+    }
+}
+
+fn prepare_kernel(queue: Res<RenderQueue>, buffer: Res<KernelBuffer>, options: Res<Options>) {
+    if options.is_changed() {
+        let kernel = GpuKernel::new(options.kernel_radius, options.kernel_sigma);
+        queue.write_buffer(&buffer, 0, bytemuck::bytes_of(&kernel));
+    }
+}
 
 #[derive(Resource, Deref, DerefMut)]
 pub struct DirectionBindGroupLayout(BindGroupLayout);
@@ -24,35 +88,48 @@ impl FromWorld for DirectionBindGroups {
     fn from_world(world: &mut World) -> Self {
         let device: &RenderDevice = world.resource::<RenderDevice>();
         let layout = world.resource::<DirectionBindGroupLayout>();
+        let kernel = world.resource::<KernelBuffer>();
 
         let vertical = device.create_bind_group(&BindGroupDescriptor {
             label: "DirectionBindGroups_vertical".into(),
             layout,
-            entries: &[BindGroupEntry {
-                binding: 0,
-                resource: device
-                    .create_buffer_with_data(&BufferInitDescriptor {
-                        label: "IVec2{x: 0, y: 1}".into(),
-                        contents: bytemuck::bytes_of(&IVec2::new(0, 1)),
-                        usage: BufferUsages::COPY_DST | BufferUsages::UNIFORM,
-                    })
-                    .as_entire_binding(),
-            }],
+            entries: &[
+                BindGroupEntry {
+                    binding: 0,
+                    resource: device
+                        .create_buffer_with_data(&BufferInitDescriptor {
+                            label: "IVec2{x: 0, y: 1}".into(),
+                            contents: bytemuck::bytes_of(&IVec2::new(0, 1)),
+                            usage: BufferUsages::COPY_DST | BufferUsages::UNIFORM,
+                        })
+                        .as_entire_binding(),
+                },
+                BindGroupEntry {
+                    binding: 1,
+                    resource: kernel.as_entire_binding(),
+                },
+            ],
         });
 
         let horizontal = device.create_bind_group(&BindGroupDescriptor {
             label: "DirectionBindGroups_horizontal".into(),
             layout,
-            entries: &[BindGroupEntry {
-                binding: 0,
-                resource: device
-                    .create_buffer_with_data(&BufferInitDescriptor {
-                        label: "IVec2{x: 1, y: 0}".into(),
-                        contents: bytemuck::bytes_of(&IVec2::new(1, 0)),
-                        usage: BufferUsages::COPY_DST | BufferUsages::UNIFORM,
-                    })
-                    .as_entire_binding(),
-            }],
+            entries: &[
+                BindGroupEntry {
+                    binding: 0,
+                    resource: device
+                        .create_buffer_with_data(&BufferInitDescriptor {
+                            label: "IVec2{x: 1, y: 0}".into(),
+                            contents: bytemuck::bytes_of(&IVec2::new(1, 0)),
+                            usage: BufferUsages::COPY_DST | BufferUsages::UNIFORM,
+                        })
+                        .as_entire_binding(),
+                },
+                BindGroupEntry {
+                    binding: 1,
+                    resource: kernel.as_entire_binding(),
+                },
+            ],
         });
 
         Self {
@@ -67,18 +144,34 @@ impl FromWorld for DirectionBindGroupLayout {
         let device: &RenderDevice = world.resource::<RenderDevice>();
         let layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
             label: "DirectionBindGroupLayout".into(),
-            entries: &[BindGroupLayoutEntry {
-                binding: 0,
-                visibility: ShaderStages::FRAGMENT,
-                ty: BindingType::Buffer {
-                    ty: BufferBindingType::Uniform,
-                    has_dynamic_offset: false,
-                    min_binding_size: Some(
-                        (std::mem::size_of::<IVec2>() as u64).try_into().unwrap(),
-                    ),
+            entries: &[
+                BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: ShaderStages::FRAGMENT,
+                    ty: BindingType::Buffer {
+                        ty: BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: Some(
+                            (std::mem::size_of::<IVec2>() as u64).try_into().unwrap(),
+                        ),
+                    },
+                    count: None,
+                },
+                BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: ShaderStages::FRAGMENT,
+                    ty: BindingType::Buffer {
+                        ty: BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: Some(
+                            (std::mem::size_of::<GpuKernel>() as u64)
+                                .try_into()
+                                .unwrap(),
+                        ),
+                    },
+                    count: None,
                 },
-                count: None,
-            }],
+            ],
         });
         Self(layout)
     }
@@ -89,7 +182,9 @@ pub struct Plugin;
 impl bevy::app::Plugin for Plugin {
     fn build(&self, app: &mut App) {
         app.sub_app_mut(RenderApp)
+            .init_resource::<KernelBuffer>()
             .init_resource::<DirectionBindGroupLayout>()
-            .init_resource::<DirectionBindGroups>();
+            .init_resource::<DirectionBindGroups>()
+            .add_system(prepare_kernel.in_set(RenderSet::Prepare));
     }
 }
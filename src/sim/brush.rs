@@ -0,0 +1,325 @@
+use bevy::{
+    prelude::*,
+    render::{
+        render_resource::{
+            BindGroup, BindGroupDescriptor, BindGroupEntry, BindGroupLayout,
+            BindGroupLayoutDescriptor, BindGroupLayoutEntry, BindingType, Buffer,
+            BufferBindingType, BufferDescriptor, BufferInitDescriptor, BufferUsages, ShaderStages,
+            TextureDimension, TextureFormat,
+        },
+        renderer::{RenderDevice, RenderQueue},
+        texture::BevyDefault,
+        Extract, RenderApp,
+    },
+};
+use bytemuck::{Pod, Zeroable};
+
+use super::{species, trail, Obstacles};
+use crate::Resolution;
+
+/// The maximum number of [BrushStroke]s applied per frame. Any more are dropped.
+pub const MAX_BRUSH_STROKES: usize = 16;
+
+/// What a [BrushStroke] paints.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BrushMode {
+    /// Deposits the chemical of a species into its trail.
+    Deposit(Entity),
+    /// Paints attractant into the nutrient field, which every species senses.
+    Attract,
+    /// Paints repellent into the nutrient field, which every species senses.
+    Repel,
+    /// Removes a fraction `strength` of the trails and of the nutrient field.
+    Erase,
+    /// Stamps an obstacle into the [Obstacles] mask, creating the mask if there is none. Only
+    /// masks with 8 bits per RGBA channel can be painted.
+    Obstacle,
+}
+
+/// A dab of the brush, which is applied before the next step. Its strength fades from the center
+/// to the edge.
+#[derive(Clone, Copy, Debug)]
+pub struct BrushStroke {
+    pub mode: BrushMode,
+    /// The center of the stroke, as a fraction of the world's width and height.
+    pub position: Vec2,
+    /// Distance from the center, in units of the world's height.
+    pub radius: f32,
+    /// The amount of chemical or nutrient added at the center, or the fraction erased.
+    pub strength: f32,
+}
+
+/// The strokes painted this frame. Push strokes during [CoreSet::Update]; they are applied and
+/// cleared at the end of the frame.
+#[derive(Resource, Clone, Debug, Default, Deref, DerefMut)]
+pub struct BrushStrokes(pub Vec<BrushStroke>);
+
+fn clear_brush_strokes(mut strokes: ResMut<BrushStrokes>) {
+    strokes.clear();
+}
+
+/// Paints the obstacle strokes into the [Obstacles] mask on the CPU, since the mask can be any
+/// image.
+fn stamp_obstacles(
+    strokes: Res<BrushStrokes>,
+    resolution: Res<Resolution>,
+    mut obstacles: ResMut<Obstacles>,
+    mut images: ResMut<Assets<Image>>,
+) {
+    let mut strokes = strokes
+        .iter()
+        .filter(|stroke| stroke.mode == BrushMode::Obstacle)
+        .peekable();
+    if strokes.peek().is_none() {
+        return;
+    }
+    let handle = obstacles.get_or_insert_with(|| {
+        // white everywhere, i.e. without any obstacles
+        images.add(Image::new_fill(
+            resolution.extent(1),
+            TextureDimension::D2,
+            &[255; 4],
+            TextureFormat::bevy_default(),
+        ))
+    });
+    let Some(image) = images.get_mut(handle) else {
+        // still loading
+        return;
+    };
+    if !matches!(
+        image.texture_descriptor.format,
+        TextureFormat::Rgba8Unorm | TextureFormat::Rgba8UnormSrgb
+    ) {
+        warn!(
+            "can't paint obstacles into a {:?} mask",
+            image.texture_descriptor.format
+        );
+        return;
+    }
+    let size = image.size();
+    // texels are square in world units only if the mask has the world's aspect ratio
+    let world_size = Vec2::new(resolution.aspect_ratio(), 1.0);
+    for stroke in strokes {
+        let min = ((stroke.position - stroke.radius / world_size) * size)
+            .floor()
+            .max(Vec2::ZERO);
+        let max = ((stroke.position + stroke.radius / world_size) * size)
+            .ceil()
+            .min(size);
+        for y in min.y as usize..max.y as usize {
+            for x in min.x as usize..max.x as usize {
+                let texel = (Vec2::new(x as f32, y as f32) + 0.5) / size;
+                if ((texel - stroke.position) * world_size).length() <= stroke.radius {
+                    let offset = (y * size.x as usize + x) * 4;
+                    image.data[offset..offset + 3].fill(0);
+                }
+            }
+        }
+    }
+}
+
+// must match the `BRUSH_*` constants in simulate.wgsl
+const BRUSH_DEPOSIT: u32 = 0;
+const BRUSH_NUTRIENT: u32 = 1;
+const BRUSH_ERASE: u32 = 2;
+
+#[derive(Copy, Clone, Default, Pod, Zeroable)]
+#[repr(C)]
+struct GpuBrushStroke {
+    position: Vec2,
+    radius: f32,
+    strength: f32,
+    slot: u32,
+    mode: u32,
+    _padding: [u32; 2],
+}
+
+#[derive(Copy, Clone, Pod, Zeroable)]
+#[repr(C)]
+struct GpuBrush {
+    count: u32,
+    _padding: [u32; 3],
+    strokes: [GpuBrushStroke; MAX_BRUSH_STROKES],
+}
+
+#[derive(Resource, Deref)]
+/// Holds the strokes painted this frame, except obstacles.
+struct BrushBuffer(Buffer);
+
+impl FromWorld for BrushBuffer {
+    fn from_world(world: &mut World) -> Self {
+        let device: &RenderDevice = world.resource::<RenderDevice>();
+        let buffer = device.create_buffer(&BufferDescriptor {
+            label: "brush::BrushBuffer".into(),
+            size: std::mem::size_of::<GpuBrush>() as u64,
+            usage: BufferUsages::COPY_DST | BufferUsages::UNIFORM,
+            mapped_at_creation: false,
+        });
+        Self(buffer)
+    }
+}
+
+#[derive(Resource, Default)]
+/// Which targets the strokes painted this frame touch. Lives in the Render world.
+pub(crate) struct PendingStrokes {
+    /// The layers of the trail map to repaint.
+    pub(crate) layers: [bool; trail::LAYERS],
+    pub(crate) nutrients: bool,
+}
+
+fn render_extract_brush_strokes(
+    queue: Res<RenderQueue>,
+    buffer: Res<BrushBuffer>,
+    slots: Res<species::Slots>,
+    mut pending: ResMut<PendingStrokes>,
+    strokes: Extract<Res<BrushStrokes>>,
+) {
+    *pending = PendingStrokes::default();
+    let mut brush = GpuBrush::zeroed();
+    // obstacles are painted in the main world
+    let strokes = strokes
+        .iter()
+        .filter(|stroke| stroke.mode != BrushMode::Obstacle);
+    for (i, stroke) in strokes.enumerate() {
+        if i == MAX_BRUSH_STROKES {
+            warn!(
+                "more than {} brush strokes in one frame; dropping the rest",
+                MAX_BRUSH_STROKES
+            );
+            break;
+        }
+        let (mode, slot, strength) = match stroke.mode {
+            BrushMode::Deposit(id) => {
+                let Some(slot) = slots.get(id) else {
+                    continue;
+                };
+                pending.layers[slot / trail::SPECIES_PER_LAYER] = true;
+                (BRUSH_DEPOSIT, slot as u32, stroke.strength)
+            }
+            BrushMode::Attract => {
+                pending.nutrients = true;
+                (BRUSH_NUTRIENT, 0, stroke.strength)
+            }
+            BrushMode::Repel => {
+                pending.nutrients = true;
+                (BRUSH_NUTRIENT, 0, -stroke.strength)
+            }
+            BrushMode::Erase => {
+                pending.layers = [true; trail::LAYERS];
+                pending.nutrients = true;
+                (BRUSH_ERASE, 0, stroke.strength)
+            }
+            BrushMode::Obstacle => unreachable!(),
+        };
+        brush.strokes[brush.count as usize] = GpuBrushStroke {
+            position: stroke.position,
+            radius: stroke.radius,
+            strength,
+            slot,
+            mode,
+            _padding: Default::default(),
+        };
+        brush.count += 1;
+    }
+    if brush.count > 0 {
+        queue.write_buffer(&buffer, 0, bytemuck::bytes_of(&brush));
+    }
+}
+
+#[derive(Resource, Deref, DerefMut)]
+pub(crate) struct BrushBindGroupLayout(BindGroupLayout);
+
+impl FromWorld for BrushBindGroupLayout {
+    fn from_world(world: &mut World) -> Self {
+        let device: &RenderDevice = world.resource::<RenderDevice>();
+        let layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+            label: "BrushBindGroupLayout".into(),
+            entries: &[
+                BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: ShaderStages::COMPUTE | ShaderStages::FRAGMENT,
+                    ty: BindingType::Buffer {
+                        ty: BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: Some(
+                            (std::mem::size_of::<GpuBrush>() as u64).try_into().unwrap(),
+                        ),
+                    },
+                    count: None,
+                },
+                BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: ShaderStages::FRAGMENT,
+                    ty: BindingType::Buffer {
+                        ty: BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: Some(
+                            (std::mem::size_of::<u32>() as u64).try_into().unwrap(),
+                        ),
+                    },
+                    count: None,
+                },
+            ],
+        });
+        Self(layout)
+    }
+}
+
+#[derive(Resource, Deref)]
+/// Binds the strokes along with the layer of the trail map they are applied to, indexed by layer.
+/// The nutrient field can use any of them.
+pub(crate) struct BrushBindGroups(Vec<BindGroup>);
+
+impl FromWorld for BrushBindGroups {
+    fn from_world(world: &mut World) -> Self {
+        let device: &RenderDevice = world.resource::<RenderDevice>();
+        let layout = world.resource::<BrushBindGroupLayout>();
+        let buffer = world.resource::<BrushBuffer>();
+        let bind_groups = (0..trail::LAYERS as u32)
+            .map(|layer| {
+                device.create_bind_group(&BindGroupDescriptor {
+                    label: Some(&format!("brush::BrushBindGroup[{}]", layer)),
+                    layout,
+                    entries: &[
+                        BindGroupEntry {
+                            binding: 0,
+                            resource: buffer.as_entire_binding(),
+                        },
+                        BindGroupEntry {
+                            binding: 1,
+                            resource: device
+                                .create_buffer_with_data(&BufferInitDescriptor {
+                                    label: Some(&format!("brush::LayerBuffer[{}]", layer)),
+                                    contents: bytemuck::bytes_of(&layer),
+                                    usage: BufferUsages::UNIFORM,
+                                })
+                                .as_entire_binding(),
+                        },
+                    ],
+                })
+            })
+            .collect();
+        Self(bind_groups)
+    }
+}
+
+pub(crate) struct Plugin;
+
+impl bevy::app::Plugin for Plugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<BrushStrokes>()
+            .add_system(clear_brush_strokes.in_base_set(CoreSet::First))
+            .add_system(stamp_obstacles.in_base_set(CoreSet::PostUpdate));
+
+        app.sub_app_mut(RenderApp)
+            .init_resource::<BrushBuffer>()
+            .init_resource::<BrushBindGroupLayout>()
+            .init_resource::<BrushBindGroups>()
+            .init_resource::<PendingStrokes>()
+            .add_system(
+                render_extract_brush_strokes
+                    .in_schedule(ExtractSchedule)
+                    .after(species::render_extract_slots),
+            );
+    }
+}
@@ -0,0 +1,111 @@
+use bevy::{
+    prelude::*,
+    render::{
+        render_resource::{
+            BindGroupDescriptor, BindGroupEntry, BindGroupLayoutDescriptor, BindGroupLayoutEntry,
+            BindingType, BufferBindingType, BufferDescriptor, BufferUsages, ShaderStages,
+        },
+        renderer::{RenderDevice, RenderQueue},
+        Extract, RenderApp,
+    },
+};
+
+use super::species::{self, Qualities, MAX_SPECIES};
+
+#[derive(Resource, Deref)]
+/// The display color of each species slot, used to colorize the chemical trails.
+struct Palette(bevy::render::render_resource::Buffer);
+
+impl FromWorld for Palette {
+    fn from_world(world: &mut World) -> Self {
+        let device: &RenderDevice = world.resource::<RenderDevice>();
+        let buffer = device.create_buffer(&BufferDescriptor {
+            label: "display::Palette".into(),
+            size: (MAX_SPECIES * std::mem::size_of::<Vec4>()) as u64,
+            usage: BufferUsages::COPY_DST | BufferUsages::UNIFORM,
+            mapped_at_creation: false,
+        });
+        Self(buffer)
+    }
+}
+
+fn render_extract_palette(
+    queue: Res<RenderQueue>,
+    palette: Res<Palette>,
+    slots: Res<species::Slots>,
+    query: Extract<Query<(Entity, Ref<Qualities>)>>,
+) {
+    if !slots.is_changed() && !query.iter().any(|(_, qualities)| qualities.is_changed()) {
+        return;
+    }
+    let mut colors = [Vec4::ZERO; MAX_SPECIES];
+    for (id, qualities) in &query {
+        if let Some(slot) = slots.get(id) {
+            colors[slot] = Vec4::from_array(qualities.color.as_rgba_f32());
+        }
+    }
+    queue.write_buffer(&palette, 0, bytemuck::cast_slice(&colors));
+}
+
+#[derive(Resource, Deref)]
+pub(crate) struct BindGroupLayout(bevy::render::render_resource::BindGroupLayout);
+
+impl FromWorld for BindGroupLayout {
+    fn from_world(world: &mut World) -> Self {
+        let device: &RenderDevice = world.resource::<RenderDevice>();
+        let layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+            label: "PaletteBindGroupLayout".into(),
+            entries: &[BindGroupLayoutEntry {
+                binding: 0,
+                visibility: ShaderStages::FRAGMENT,
+                ty: BindingType::Buffer {
+                    ty: BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: Some(
+                        ((MAX_SPECIES * std::mem::size_of::<Vec4>()) as u64)
+                            .try_into()
+                            .unwrap(),
+                    ),
+                },
+                count: None,
+            }],
+        });
+        Self(layout)
+    }
+}
+
+#[derive(Resource, Deref)]
+pub(crate) struct BindGroup(bevy::render::render_resource::BindGroup);
+
+impl FromWorld for BindGroup {
+    fn from_world(world: &mut World) -> Self {
+        let device: &RenderDevice = world.resource();
+        let layout: &BindGroupLayout = world.resource();
+        let palette: &Palette = world.resource();
+        let bind_group = device.create_bind_group(&BindGroupDescriptor {
+            label: "display::BindGroup".into(),
+            layout,
+            entries: &[BindGroupEntry {
+                binding: 0,
+                resource: palette.as_entire_binding(),
+            }],
+        });
+        Self(bind_group)
+    }
+}
+
+pub(crate) struct Plugin;
+
+impl bevy::app::Plugin for Plugin {
+    fn build(&self, app: &mut App) {
+        app.sub_app_mut(RenderApp)
+            .init_resource::<Palette>()
+            .init_resource::<BindGroupLayout>()
+            .init_resource::<BindGroup>()
+            .add_system(
+                render_extract_palette
+                    .in_schedule(ExtractSchedule)
+                    .after(species::render_extract_slots),
+            );
+    }
+}
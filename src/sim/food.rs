@@ -0,0 +1,196 @@
+use bevy::{
+    prelude::*,
+    render::{
+        render_resource::{
+            BindGroup, BindGroupDescriptor, BindGroupEntry, BindGroupLayout,
+            BindGroupLayoutDescriptor, BindGroupLayoutEntry, BindingType, BufferBindingType,
+            BufferDescriptor, BufferSize, BufferUsages, ShaderStages,
+        },
+        renderer::{RenderDevice, RenderQueue},
+        Extract, RenderApp, RenderSet,
+    },
+};
+use bytemuck::{Pod, Zeroable};
+
+use crate::Resolution;
+
+/// The maximum number of [FoodSource]s in the world. Any more are ignored.
+pub const MAX_FOOD_SOURCES: usize = 64;
+
+/// A static source of attractant that every species senses on top of the trails, or a repellent if
+/// its strength is negative.
+///
+/// The source sits at the `x` and `y` of the entity's [Transform], which, like agent positions, are
+/// fractions of the world's width and height. Distances are in units of the world's height.
+#[derive(Component, Clone, Debug)]
+pub struct FoodSource {
+    /// Distance from the center within which the source has its full strength. Zero for a point
+    /// source.
+    pub radius: f32,
+    /// Distance beyond `radius` over which the strength fades to zero. Zero for a hard-edged disc.
+    pub falloff: f32,
+    /// Added to what agents sense, like a trail of the same strength that they are attracted to
+    /// with a weight of one. Negative strengths repel agents.
+    pub strength: f32,
+}
+
+impl Default for FoodSource {
+    fn default() -> Self {
+        Self {
+            radius: 0.02,
+            falloff: 0.02,
+            strength: 1.0,
+        }
+    }
+}
+
+#[derive(Bundle, Default)]
+pub struct FoodSourceBundle {
+    pub source: FoodSource,
+    pub transform: Transform,
+}
+
+#[derive(Copy, Clone, Default, Pod, Zeroable)]
+#[repr(C)]
+struct GpuFoodSource {
+    center: Vec2,
+    radius: f32,
+    falloff: f32,
+    strength: f32,
+    _padding: [f32; 3],
+}
+
+#[derive(Copy, Clone, Pod, Zeroable)]
+#[repr(C)]
+struct GpuFoodSources {
+    count: u32,
+    _padding: [u32; 3],
+    sources: [GpuFoodSource; MAX_FOOD_SOURCES],
+}
+
+#[derive(Resource, Deref)]
+/// Holds every [FoodSource], bound next to the [Options](super::Options). Lives in the Render
+/// world.
+pub(crate) struct Buffer(bevy::render::render_resource::Buffer);
+
+impl Buffer {
+    pub(crate) const SIZE: usize = std::mem::size_of::<GpuFoodSources>();
+}
+
+impl FromWorld for Buffer {
+    fn from_world(world: &mut World) -> Self {
+        let device: &RenderDevice = world.resource::<RenderDevice>();
+        let buffer = device.create_buffer(&BufferDescriptor {
+            label: "food::Buffer".into(),
+            size: Self::SIZE as u64,
+            usage: BufferUsages::COPY_DST | BufferUsages::UNIFORM,
+            // starts out zeroed, i.e. without any sources
+            mapped_at_creation: false,
+        });
+        Self(buffer)
+    }
+}
+
+fn render_extract_food_sources(
+    queue: Res<RenderQueue>,
+    buffer: Res<Buffer>,
+    mut removed: Extract<RemovedComponents<FoodSource>>,
+    query: Extract<Query<(Ref<FoodSource>, Ref<Transform>)>>,
+) {
+    let changed = query
+        .iter()
+        .any(|(source, transform)| source.is_changed() || transform.is_changed());
+    if !changed && removed.iter().next().is_none() {
+        return;
+    }
+    let count = query.iter().count();
+    if count > MAX_FOOD_SOURCES {
+        warn!(
+            "{} food sources exceed the limit of {}; ignoring the rest",
+            count, MAX_FOOD_SOURCES
+        );
+    }
+    let mut sources = GpuFoodSources::zeroed();
+    for (gpu, (source, transform)) in sources.sources.iter_mut().zip(&query) {
+        *gpu = GpuFoodSource {
+            center: transform.translation.truncate(),
+            radius: source.radius,
+            falloff: source.falloff,
+            strength: source.strength,
+            _padding: Default::default(),
+        };
+    }
+    sources.count = count.min(MAX_FOOD_SOURCES) as u32;
+    queue.write_buffer(&buffer, 0, bytemuck::bytes_of(&sources));
+}
+
+/// The nutrient field holds one value per texel, which agents sense like [FoodSource]s.
+const NUTRIENT_SIZE: u64 = std::mem::size_of::<f32>() as u64;
+
+#[derive(Resource, Deref, DerefMut)]
+pub(crate) struct NutrientBindGroupLayout(BindGroupLayout);
+
+impl FromWorld for NutrientBindGroupLayout {
+    fn from_world(world: &mut World) -> Self {
+        let device: &RenderDevice = world.resource::<RenderDevice>();
+        let layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+            label: "NutrientBindGroupLayout".into(),
+            entries: &[BindGroupLayoutEntry {
+                binding: 0,
+                // agents sense the field, and the brush paints it
+                visibility: ShaderStages::COMPUTE,
+                ty: BindingType::Buffer {
+                    ty: BufferBindingType::Storage { read_only: false },
+                    has_dynamic_offset: false,
+                    min_binding_size: BufferSize::new(NUTRIENT_SIZE),
+                },
+                count: None,
+            }],
+        });
+        Self(layout)
+    }
+}
+
+#[derive(Resource, Deref)]
+/// Binds the nutrient field painted with the brush (see [BrushMode](super::BrushMode)), as
+/// attractant (positive) or repellent (negative) per texel of the trail map.
+pub(crate) struct NutrientBindGroup(BindGroup);
+
+/// Recreates the nutrient field, empty, whenever the [Resolution] changes.
+fn prepare_nutrient_bind_group(
+    mut commands: Commands,
+    resolution: Res<Resolution>,
+    layout: Res<NutrientBindGroupLayout>,
+    device: Res<RenderDevice>,
+) {
+    if !resolution.is_changed() {
+        return;
+    }
+    let buffer = device.create_buffer(&BufferDescriptor {
+        label: "food::NutrientBuffer".into(),
+        size: resolution.x as u64 * resolution.y as u64 * NUTRIENT_SIZE,
+        usage: BufferUsages::STORAGE,
+        mapped_at_creation: false,
+    });
+    let bind_group = device.create_bind_group(&BindGroupDescriptor {
+        label: "food::NutrientBindGroup".into(),
+        layout: &layout,
+        entries: &[BindGroupEntry {
+            binding: 0,
+            resource: buffer.as_entire_binding(),
+        }],
+    });
+    commands.insert_resource(NutrientBindGroup(bind_group));
+}
+
+pub(crate) struct Plugin;
+
+impl bevy::app::Plugin for Plugin {
+    fn build(&self, app: &mut App) {
+        app.sub_app_mut(RenderApp)
+            .init_resource::<Buffer>()
+            .init_resource::<NutrientBindGroupLayout>()
+            .add_system(render_extract_food_sources.in_schedule(ExtractSchedule))
+            .add_system(prepare_nutrient_bind_group.in_set(RenderSet::Prepare));
+    }
+}
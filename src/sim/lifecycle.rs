@@ -0,0 +1,250 @@
+use std::sync::{Arc, Mutex};
+
+use bevy::{
+    prelude::*,
+    render::{
+        render_resource::{Buffer, BufferDescriptor, BufferUsages, CommandEncoder, MapMode},
+        renderer::RenderDevice,
+        RenderApp, RenderSet,
+    },
+    utils::HashMap,
+};
+use bytemuck::{Pod, Zeroable};
+
+use super::species::{self, MAX_SPECIES};
+
+/// Gives agents energy, which drains over time and is replenished by food (see
+/// [FoodSource](super::FoodSource) and [BrushMode::Attract](super::BrushMode::Attract)). Agents
+/// die when they run out of energy, and split in two once they have `split_energy`, so the
+/// population grows and shrinks on the GPU.
+///
+/// [NumAgents](species::NumAgents) is the population the species spawns with, and [LiveAgents]
+/// reports the current one. Species with a lifecycle compile their own pipelines, and adding or
+/// removing the lifecycle reinitializes the species' agents.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Lifecycle {
+    /// The most agents the species can have. Agents born beyond it don't survive.
+    pub capacity: u32,
+    /// The energy agents spawn with.
+    pub initial_energy: f32,
+    /// Energy every agent spends per second.
+    pub drain: f32,
+    /// Energy per second an agent gains per unit of food where it is. Repellents take energy.
+    pub intake: f32,
+    /// Agents with at least this much energy split into two agents with half the energy each, if
+    /// there is room for the second one. Otherwise they keep their energy.
+    pub split_energy: f32,
+}
+
+impl Default for Lifecycle {
+    fn default() -> Self {
+        Self {
+            capacity: 100_000,
+            initial_energy: 1.0,
+            drain: 0.1,
+            intake: 1.0,
+            split_energy: 2.0,
+        }
+    }
+}
+
+/// The number of live agents of every species with a [Lifecycle], read back from the GPU a few
+/// frames late. Shared with the render world, which sets it.
+#[derive(Resource, Clone, Default)]
+pub struct LiveAgents(Arc<Mutex<HashMap<Entity, u32>>>);
+
+impl LiveAgents {
+    /// Returns the number of live agents of a species, if it has a [Lifecycle] and its agents were
+    /// counted.
+    pub fn get(&self, species: Entity) -> Option<u32> {
+        self.0.lock().unwrap().get(&species).copied()
+    }
+}
+
+/// The population of a species with a lifecycle; all zeros for other species.
+#[derive(Copy, Clone, Default, Pod, Zeroable)]
+#[repr(C)]
+pub(crate) struct GpuPopulation {
+    /// The indirect dispatch that runs one invocation per live agent.
+    workgroups: [u32; 3],
+    /// The species' agents `start..start + live` are alive.
+    live: u32,
+    /// The agents that survive or are born during a step, up to the capacity.
+    next: u32,
+    /// The size of the species' range of agents.
+    capacity: u32,
+    /// The agents that survive a step, which are packed before the offspring.
+    survivors: u32,
+    _padding: u32,
+}
+
+const POPULATION_SIZE: u64 = std::mem::size_of::<GpuPopulation>() as u64;
+
+impl GpuPopulation {
+    /// The population of a species that spawns with `live` agents.
+    pub(crate) fn new(live: u32, capacity: u32) -> Self {
+        Self {
+            workgroups: [live.div_ceil(super::WORKGROUP_SIZE), 1, 1],
+            live,
+            next: live,
+            capacity,
+            survivors: live,
+            _padding: 0,
+        }
+    }
+
+    /// The offset of the population of the species in `slot`, which is also the offset of its
+    /// indirect dispatch in the [IndirectBuffer].
+    pub(crate) fn offset(slot: usize) -> u64 {
+        slot as u64 * POPULATION_SIZE
+    }
+
+    /// The offset of the capacity of the species in `slot`, which can be updated on its own when
+    /// its agents move within the agent buffer.
+    pub(crate) fn capacity_offset(slot: usize) -> u64 {
+        Self::offset(slot) + std::mem::offset_of!(Self, capacity) as u64
+    }
+}
+
+#[derive(Resource, Deref)]
+/// The [GpuPopulation] of every species, indexed by slot. Lives in the Render world.
+pub(crate) struct PopulationBuffer(Buffer);
+
+impl FromWorld for PopulationBuffer {
+    fn from_world(world: &mut World) -> Self {
+        let device: &RenderDevice = world.resource();
+        Self(device.create_buffer(&BufferDescriptor {
+            label: "lifecycle::PopulationBuffer".into(),
+            usage: BufferUsages::STORAGE | BufferUsages::COPY_SRC | BufferUsages::COPY_DST,
+            size: MAX_SPECIES as u64 * POPULATION_SIZE,
+            // starts out zeroed, i.e. without any species with a lifecycle
+            mapped_at_creation: false,
+        }))
+    }
+}
+
+#[derive(Resource, Deref)]
+/// A copy of the [PopulationBuffer] to dispatch indirectly from, since a buffer can't be used for
+/// indirect dispatches while it is bound as writable storage. Lives in the Render world.
+pub(crate) struct IndirectBuffer(Buffer);
+
+impl FromWorld for IndirectBuffer {
+    fn from_world(world: &mut World) -> Self {
+        let device: &RenderDevice = world.resource();
+        Self(device.create_buffer(&BufferDescriptor {
+            label: "lifecycle::IndirectBuffer".into(),
+            usage: BufferUsages::INDIRECT | BufferUsages::COPY_DST,
+            size: MAX_SPECIES as u64 * POPULATION_SIZE,
+            mapped_at_creation: false,
+        }))
+    }
+}
+
+impl IndirectBuffer {
+    /// Copies the indirect dispatches out of the populations, after they were written.
+    pub(crate) fn copy(&self, encoder: &mut CommandEncoder, populations: &PopulationBuffer) {
+        encoder.copy_buffer_to_buffer(populations, 0, self, 0, self.size());
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum ReadbackState {
+    /// The staging buffer is free.
+    Idle,
+    /// The populations are being copied into the staging buffer.
+    Copied,
+    /// Waiting for the staging buffer to map.
+    Mapping,
+    Mapped,
+}
+
+#[derive(Resource)]
+/// Copies the [PopulationBuffer] somewhere the CPU can read it, one frame at a time. Lives in the
+/// Render world.
+pub(crate) struct Readback {
+    buffer: Buffer,
+    state: Arc<Mutex<ReadbackState>>,
+}
+
+impl FromWorld for Readback {
+    fn from_world(world: &mut World) -> Self {
+        let device: &RenderDevice = world.resource();
+        Self {
+            buffer: device.create_buffer(&BufferDescriptor {
+                label: "lifecycle::Readback".into(),
+                usage: BufferUsages::MAP_READ | BufferUsages::COPY_DST,
+                size: MAX_SPECIES as u64 * POPULATION_SIZE,
+                mapped_at_creation: false,
+            }),
+            state: Arc::new(Mutex::new(ReadbackState::Idle)),
+        }
+    }
+}
+
+impl Readback {
+    /// Copies the populations into the staging buffer, unless the previous copy is still being
+    /// read.
+    pub(crate) fn copy(&self, encoder: &mut CommandEncoder, populations: &PopulationBuffer) {
+        let mut state = self.state.lock().unwrap();
+        if *state != ReadbackState::Idle {
+            return;
+        }
+        encoder.copy_buffer_to_buffer(populations, 0, &self.buffer, 0, self.buffer.size());
+        *state = ReadbackState::Copied;
+    }
+}
+
+/// Maps the staging buffer once the frame that copied into it is submitted, and publishes the live
+/// counts once it is mapped.
+fn render_read_back_populations(
+    readback: Res<Readback>,
+    device: Res<RenderDevice>,
+    slots: Res<species::Slots>,
+    live_agents: Res<LiveAgents>,
+) {
+    let state = *readback.state.lock().unwrap();
+    match state {
+        ReadbackState::Copied => {
+            *readback.state.lock().unwrap() = ReadbackState::Mapping;
+            let shared = readback.state.clone();
+            device.map_buffer(&readback.buffer.slice(..), MapMode::Read, move |result| {
+                *shared.lock().unwrap() = match result {
+                    Ok(()) => ReadbackState::Mapped,
+                    // try again with the next copy
+                    Err(_) => ReadbackState::Idle,
+                };
+            });
+        }
+        ReadbackState::Mapped => {
+            {
+                let data = readback.buffer.slice(..).get_mapped_range();
+                let populations: &[GpuPopulation] = bytemuck::cast_slice(&data);
+                *live_agents.0.lock().unwrap() = slots
+                    .iter()
+                    .filter(|(_, slot)| populations[*slot].capacity > 0)
+                    .map(|(id, slot)| (id, populations[slot].live))
+                    .collect();
+            }
+            readback.buffer.unmap();
+            *readback.state.lock().unwrap() = ReadbackState::Idle;
+        }
+        ReadbackState::Idle | ReadbackState::Mapping => {}
+    }
+}
+
+pub(crate) struct Plugin;
+
+impl bevy::app::Plugin for Plugin {
+    fn build(&self, app: &mut App) {
+        let live_agents = LiveAgents::default();
+        app.insert_resource(live_agents.clone());
+
+        app.sub_app_mut(RenderApp)
+            // shares the counts with the main world
+            .insert_resource(live_agents)
+            .init_resource::<PopulationBuffer>()
+            .init_resource::<IndirectBuffer>()
+            .init_resource::<Readback>()
+            .add_system(render_read_back_populations.in_set(RenderSet::Cleanup));
+    }
+}
@@ -1,35 +1,109 @@
+mod behavior;
 mod blur;
+mod brush;
+mod display;
+mod food;
+mod lifecycle;
 mod options;
 mod seed;
 pub mod species;
+mod time;
 pub mod trail;
 
+pub use behavior::{Behavior, BehaviorModule, Behaviors, Hook};
+pub use blur::MAX_KERNEL_RADIUS;
+pub use brush::{BrushMode, BrushStroke, BrushStrokes, MAX_BRUSH_STROKES};
+pub use food::{FoodSource, FoodSourceBundle, MAX_FOOD_SOURCES};
+pub use lifecycle::{Lifecycle, LiveAgents};
 pub use options::*;
+pub use seed::{SimulationSeed, SimulationStep};
 pub use species::SpeciesBundle;
+pub use time::Timestep;
+pub use trail::{Obstacles, TrailFormat};
+
+use std::sync::{Arc, Mutex};
 
 use bevy::{
+    ecs::system::SystemParam,
     prelude::*,
     render::{
+        extract_resource::{ExtractResource, ExtractResourcePlugin},
         main_graph::node::CAMERA_DRIVER,
         render_asset::RenderAssets,
         render_graph::{self, RenderGraph},
         render_resource::{
             BindGroup, BindGroupDescriptor, BindGroupLayout, BindGroupLayoutDescriptor,
-            CachedComputePipelineId, CachedRenderPipelineId, ColorTargetState, ColorWrites,
-            ComputePassDescriptor, ComputePipeline, ComputePipelineDescriptor, Face, FragmentState,
-            FrontFace, LoadOp, MultisampleState, Operations, PipelineCache, PolygonMode,
-            PrimitiveState, PrimitiveTopology, RenderPassColorAttachment, RenderPassDescriptor,
-            RenderPipeline, RenderPipelineDescriptor,
+            CachedComputePipelineId, CachedPipelineState, CachedRenderPipelineId, ColorTargetState,
+            ColorWrites, ComputePassDescriptor, ComputePipeline, ComputePipelineDescriptor,
+            Extent3d, Face, FragmentState, FrontFace, ImageCopyTexture, LoadOp, MultisampleState,
+            Operations, Origin3d, PipelineCache, PipelineCacheError, PolygonMode, PrimitiveState,
+            PrimitiveTopology, RenderPassColorAttachment, RenderPassDescriptor, RenderPipeline,
+            RenderPipelineDescriptor, ShaderDefVal, TextureAspect, TextureFormat,
         },
         renderer::RenderDevice,
         RenderApp, RenderSet,
     },
 };
 
-use crate::Framebuffers;
+use crate::{DisplayImage, Framebuffers, Resolution};
 
 const SIMULATION: &str = "simulation";
-const WORKGROUPS: UVec3 = UVec3::new(256, 1, 1);
+/// The shader every simulation pipeline runs, which [BehaviorModule]s import.
+const SHADER: &str = "shaders/simulate.wgsl";
+/// Invocations per workgroup of the agent entry points, which run one invocation per agent.
+const WORKGROUP_SIZE: u32 = 256;
+
+/// Shader definitions shared by every simulation pipeline.
+fn shader_defs() -> Vec<ShaderDefVal> {
+    vec![
+        ShaderDefVal::UInt("MAX_SPECIES".into(), species::MAX_SPECIES as u32),
+        ShaderDefVal::UInt("TRAIL_LAYERS".into(), trail::LAYERS as u32),
+        ShaderDefVal::UInt("WORKGROUP_SIZE".into(), WORKGROUP_SIZE),
+    ]
+}
+
+/// Describes a pipeline that runs `entry_point` over every texel of a `format` target.
+fn fullscreen_pipeline_descriptor(
+    label: &'static str,
+    layout: Vec<BindGroupLayout>,
+    shader: Handle<Shader>,
+    shader_defs: Vec<ShaderDefVal>,
+    entry_point: &'static str,
+    format: TextureFormat,
+) -> RenderPipelineDescriptor {
+    RenderPipelineDescriptor {
+        label: Some(label.into()),
+        layout,
+        push_constant_ranges: Vec::new(),
+        vertex: bevy::core_pipeline::fullscreen_vertex_shader::fullscreen_shader_vertex_state(),
+        primitive: PrimitiveState {
+            topology: PrimitiveTopology::TriangleStrip,
+            strip_index_format: None,
+            front_face: FrontFace::Ccw,
+            cull_mode: Some(Face::Back),
+            unclipped_depth: false,
+            polygon_mode: PolygonMode::Fill,
+            // @todo check if we can do conservative rasterization
+            conservative: false,
+        },
+        depth_stencil: None,
+        multisample: MultisampleState {
+            count: 1,
+            mask: !0,
+            alpha_to_coverage_enabled: false,
+        },
+        fragment: Some(FragmentState {
+            shader,
+            shader_defs,
+            entry_point: entry_point.into(),
+            targets: vec![Some(ColorTargetState {
+                format,
+                blend: None,
+                write_mask: ColorWrites::ALL,
+            })],
+        }),
+    }
+}
 
 #[derive(Resource, Deref, DerefMut)]
 struct EmptyBindGroupLayout(BindGroupLayout);
@@ -63,20 +137,43 @@ impl FromWorld for EmptyBindGroup {
 
 #[derive(Resource)]
 pub enum Pipelines {
-    Pending {
-        init: CachedComputePipelineId,
-        update: CachedComputePipelineId,
-        project: CachedComputePipelineId,
-        blur: CachedRenderPipelineId,
-    },
+    Pending,
     Cached {
         init: ComputePipeline,
         update: ComputePipeline,
         project: ComputePipeline,
         blur: RenderPipeline,
+        resolve: RenderPipeline,
+        display: RenderPipeline,
+        brush_trails: RenderPipeline,
+        brush_nutrients: ComputePipeline,
+        tally: ComputePipeline,
+        scan_tallies: ComputePipeline,
+        compact: ComputePipeline,
+        census: ComputePipeline,
+    },
+    /// A pipeline failed to compile, so nothing is simulated.
+    Failed {
+        error: String,
     },
 }
 
+/// The error that kept the simulation pipelines from compiling, if any. Shared with the render
+/// world, which sets it.
+#[derive(Resource, Clone, Default)]
+pub struct PipelineError(Arc<Mutex<Option<String>>>);
+
+impl PipelineError {
+    /// Returns the compilation error, if there is one.
+    pub fn get(&self) -> Option<String> {
+        self.0.lock().unwrap().clone()
+    }
+
+    fn set(&self, error: Option<String>) {
+        *self.0.lock().unwrap() = error;
+    }
+}
+
 impl Pipelines {
     /// Returns whether the pipelines are ready to run during this render pass.
     pub fn loaded(&self) -> bool {
@@ -84,149 +181,352 @@ impl Pipelines {
     }
 }
 
+/// Rebuilds the simulation pipelines whenever their shaders change, keeping the agents and trails.
+/// The [AssetPlugin] must watch for changes (see the `hot-reload` feature).
+#[derive(Resource, Clone, Copy, Debug, Default, Deref, DerefMut, ExtractResource)]
+pub struct HotReload(pub bool);
+
+/// The pipelines queued on the [PipelineCache], which recompiles them whenever their shaders
+/// change.
+#[derive(Resource, Clone, Copy)]
+struct PipelineIds {
+    init: CachedComputePipelineId,
+    update: CachedComputePipelineId,
+    project: CachedComputePipelineId,
+    blur: CachedRenderPipelineId,
+    resolve: CachedRenderPipelineId,
+    display: CachedRenderPipelineId,
+    brush_trails: CachedRenderPipelineId,
+    brush_nutrients: CachedComputePipelineId,
+    tally: CachedComputePipelineId,
+    scan_tallies: CachedComputePipelineId,
+    compact: CachedComputePipelineId,
+    census: CachedComputePipelineId,
+}
+
+impl PipelineIds {
+    fn states<'a>(&self, cache: &'a PipelineCache) -> [&'a CachedPipelineState; 12] {
+        [
+            cache.get_compute_pipeline_state(self.init),
+            cache.get_compute_pipeline_state(self.update),
+            cache.get_compute_pipeline_state(self.project),
+            cache.get_render_pipeline_state(self.blur),
+            cache.get_render_pipeline_state(self.resolve),
+            cache.get_render_pipeline_state(self.display),
+            cache.get_render_pipeline_state(self.brush_trails),
+            cache.get_compute_pipeline_state(self.brush_nutrients),
+            cache.get_compute_pipeline_state(self.tally),
+            cache.get_compute_pipeline_state(self.scan_tallies),
+            cache.get_compute_pipeline_state(self.compact),
+            cache.get_compute_pipeline_state(self.census),
+        ]
+    }
+
+    /// Returns whether any of the pipelines is waiting to be compiled.
+    fn queued(&self, cache: &PipelineCache) -> bool {
+        self.states(cache).into_iter().any(|state| {
+            matches!(
+                state,
+                CachedPipelineState::Queued
+                    | CachedPipelineState::Err(
+                        PipelineCacheError::ShaderNotLoaded(_)
+                            | PipelineCacheError::ShaderImportNotYetAvailable
+                    )
+            )
+        })
+    }
+
+    /// Returns the error of the first pipeline that failed to compile.
+    fn error(&self, cache: &PipelineCache) -> Option<String> {
+        self.states(cache)
+            .into_iter()
+            .find_map(|state| match state {
+                // the pipeline cache retries these
+                CachedPipelineState::Err(
+                    PipelineCacheError::ShaderNotLoaded(_)
+                    | PipelineCacheError::ShaderImportNotYetAvailable,
+                ) => None,
+                CachedPipelineState::Err(error) => Some(error.to_string()),
+                _ => None,
+            })
+    }
+
+    /// Returns the compiled pipelines, once all of them are ready.
+    fn cached(&self, cache: &PipelineCache) -> Option<Pipelines> {
+        Some(Pipelines::Cached {
+            init: cache.get_compute_pipeline(self.init)?.clone(),
+            update: cache.get_compute_pipeline(self.update)?.clone(),
+            project: cache.get_compute_pipeline(self.project)?.clone(),
+            blur: cache.get_render_pipeline(self.blur)?.clone(),
+            resolve: cache.get_render_pipeline(self.resolve)?.clone(),
+            display: cache.get_render_pipeline(self.display)?.clone(),
+            brush_trails: cache.get_render_pipeline(self.brush_trails)?.clone(),
+            brush_nutrients: cache.get_compute_pipeline(self.brush_nutrients)?.clone(),
+            tally: cache.get_compute_pipeline(self.tally)?.clone(),
+            scan_tallies: cache.get_compute_pipeline(self.scan_tallies)?.clone(),
+            compact: cache.get_compute_pipeline(self.compact)?.clone(),
+            census: cache.get_compute_pipeline(self.census)?.clone(),
+        })
+    }
+}
+
+/// The layouts of every bind group the simulation pipelines use.
+#[derive(SystemParam)]
+struct BindGroupLayouts<'w> {
+    empty: Res<'w, EmptyBindGroupLayout>,
+    species: Res<'w, species::BindGroupLayout>,
+    spawn: Res<'w, species::SpawnBindGroupLayout>,
+    tex: Res<'w, trail::TextureBindGroupLayout>,
+    array_tex: Res<'w, trail::ArrayTextureBindGroupLayout>,
+    palette: Res<'w, display::BindGroupLayout>,
+    deposit: Res<'w, trail::DepositBindGroupLayout>,
+    nutrients: Res<'w, food::NutrientBindGroupLayout>,
+    brush: Res<'w, brush::BrushBindGroupLayout>,
+    direction: Res<'w, blur::DirectionBindGroupLayout>,
+    options: Res<'w, options::BindGroupLayout>,
+    seed: Res<'w, seed::BindGroupLayout>,
+}
+
+/// Describes the update and project pipelines, which run the agents' [Hook]s.
+fn agent_pipeline_descriptors(
+    layouts: &BindGroupLayouts,
+    shader: Handle<Shader>,
+    shader_defs: Vec<ShaderDefVal>,
+) -> [ComputePipelineDescriptor; 2] {
+    [
+        ComputePipelineDescriptor {
+            label: Some("[SimulationPipelines] update".into()),
+            layout: vec![
+                layouts.species.clone(),
+                layouts.array_tex.clone(),
+                layouts.nutrients.clone(),
+                layouts.seed.clone(),
+                layouts.options.clone(),
+            ],
+            push_constant_ranges: Vec::new(),
+            shader: shader.clone(),
+            shader_defs: shader_defs.clone(),
+            entry_point: "update".into(),
+        },
+        ComputePipelineDescriptor {
+            label: Some("[SimulationPipelines] project".into()),
+            layout: vec![
+                layouts.species.clone(),
+                layouts.array_tex.clone(),
+                layouts.deposit.clone(),
+                layouts.seed.clone(),
+                layouts.options.clone(),
+            ],
+            push_constant_ranges: Vec::new(),
+            shader,
+            shader_defs,
+            entry_point: "project".into(),
+        },
+    ]
+}
+
 #[allow(clippy::too_many_arguments)]
 fn render_queue_pipelines(
     mut commands: Commands,
     pipelines: Option<Res<Pipelines>>,
+    pipeline_ids: Option<Res<PipelineIds>>,
     asset_server: Res<AssetServer>,
     pipeline_cache: Res<PipelineCache>,
+    pipeline_error: Res<PipelineError>,
+    hot_reload: Res<HotReload>,
+    // whether the cached pipelines are outdated
+    mut reloading: Local<bool>,
 
     // images (for getting texture format)
     gpu_images: Res<RenderAssets<Image>>,
-    framebuffers: Res<Framebuffers>,
-
-    // layouts
-    empty_bgl: Res<EmptyBindGroupLayout>,
-    species_bgl: Res<species::BindGroupLayout>,
-    tex_bgl: Res<trail::TextureBindGroupLayout>,
-    storage_tex_bgl: Res<trail::StorageTextureBindGroupLayout>,
-    direction_bgl: Res<blur::DirectionBindGroupLayout>,
-    options_bgl: Res<options::BindGroupLayout>,
-    seed_bgl: Res<seed::BindGroupLayout>,
+    trail_format: Res<TrailFormat>,
+    display_image: Res<DisplayImage>,
+
+    layouts: BindGroupLayouts,
 ) {
-    match pipelines {
+    match pipelines.zip(pipeline_ids) {
         None => {
-            
-            let shader = asset_server.load("shaders/simulate.wgsl");
+            let shader = asset_server.load(SHADER);
 
             let init = pipeline_cache.queue_compute_pipeline(ComputePipelineDescriptor {
                 label: Some("[SimulationPipelines] init_pipeline".into()),
                 layout: vec![
-                    species_bgl.clone(),
-                    empty_bgl.clone(),
-                    empty_bgl.clone(),
-                    seed_bgl.clone(),
-                    options_bgl.clone(),
+                    layouts.species.clone(),
+                    layouts.spawn.clone(),
+                    layouts.empty.clone(),
+                    layouts.seed.clone(),
+                    layouts.options.clone(),
                 ],
                 push_constant_ranges: Vec::new(),
                 shader: shader.clone(),
-                shader_defs: vec![],
+                shader_defs: shader_defs(),
                 entry_point: "init".into(),
             });
 
-            let update = pipeline_cache.queue_compute_pipeline(ComputePipelineDescriptor {
-                label: Some("[SimulationPipelines] update".into()),
-                layout: vec![
-                    species_bgl.clone(),
-                    tex_bgl.clone(),
-                    empty_bgl.clone(),
-                    seed_bgl.clone(),
-                    options_bgl.clone(),
+            let [update, project] =
+                agent_pipeline_descriptors(&layouts, shader.clone(), shader_defs())
+                    .map(|descriptor| pipeline_cache.queue_compute_pipeline(descriptor));
+
+            let blur = pipeline_cache.queue_render_pipeline(fullscreen_pipeline_descriptor(
+                "[SimulationPipelines] blur",
+                vec![
+                    layouts.empty.clone(),
+                    layouts.tex.clone(),
+                    layouts.deposit.clone(),
+                    layouts.direction.clone(),
+                    layouts.options.clone(),
                 ],
-                push_constant_ranges: Vec::new(),
-                shader: shader.clone(),
-                shader_defs: vec![],
-                entry_point: "update".into(),
-            });
-
-            let project = pipeline_cache.queue_compute_pipeline(ComputePipelineDescriptor {
-                label: Some("[SimulationPipelines] project".into()),
-                layout: vec![
-                    species_bgl.clone(),
-                    empty_bgl.clone(),
-                    storage_tex_bgl.clone(),
-                    empty_bgl.clone(),
-                    options_bgl.clone(),
+                shader.clone(),
+                shader_defs(),
+                "blur_fragment",
+                trail_format.texture_format(),
+            ));
+
+            let resolve = pipeline_cache.queue_render_pipeline(fullscreen_pipeline_descriptor(
+                "[SimulationPipelines] resolve",
+                vec![
+                    layouts.empty.clone(),
+                    layouts.tex.clone(),
+                    layouts.deposit.clone(),
+                    layouts.direction.clone(),
+                    layouts.options.clone(),
                 ],
-                push_constant_ranges: Vec::new(),
-                shader: shader.clone(),
-                shader_defs: vec![],
-                entry_point: "project".into(),
-            });
-
-            let blur = pipeline_cache.queue_render_pipeline(RenderPipelineDescriptor {
-                label: Some("[SimulationPipelines] blur".into()),
-                layout: vec![
-                    empty_bgl.clone(),
-                    tex_bgl.clone(),
-                    empty_bgl.clone(),
-                    direction_bgl.clone(),
-                    options_bgl.clone(),
+                shader.clone(),
+                shader_defs(),
+                "resolve_fragment",
+                trail_format.texture_format(),
+            ));
+
+            let display = pipeline_cache.queue_render_pipeline(fullscreen_pipeline_descriptor(
+                "[SimulationPipelines] display",
+                vec![
+                    layouts.empty.clone(),
+                    layouts.array_tex.clone(),
+                    layouts.empty.clone(),
+                    layouts.palette.clone(),
+                    layouts.options.clone(),
                 ],
-                push_constant_ranges: Vec::new(),
-                vertex:
-                    bevy::core_pipeline::fullscreen_vertex_shader::fullscreen_shader_vertex_state(),
-                primitive: PrimitiveState {
-                    topology: PrimitiveTopology::TriangleStrip,
-                    strip_index_format: None,
-                    front_face: FrontFace::Ccw,
-                    cull_mode: Some(Face::Back),
-                    unclipped_depth: false,
-                    polygon_mode: PolygonMode::Fill,
-                    // @todo check if we can do conservative rasterization
-                    conservative: false,
-                },
-                depth_stencil: None,
-                multisample: MultisampleState {
-                    count: 1,
-                    mask: !0,
-                    alpha_to_coverage_enabled: false,
-                },
-                fragment: Some(FragmentState {
-                    shader,
-                    shader_defs: Vec::new(),
-                    entry_point: "blur_fragment".into(),
-                    targets: vec![Some(ColorTargetState {
-                        format: gpu_images[&framebuffers[0]].texture_format,
-                        blend: None,
-                        write_mask: ColorWrites::ALL,
-                    })],
-                }),
-            });
+                shader.clone(),
+                shader_defs(),
+                "display_fragment",
+                gpu_images[&display_image].texture_format,
+            ));
+
+            let brush_trails =
+                pipeline_cache.queue_render_pipeline(fullscreen_pipeline_descriptor(
+                    "[SimulationPipelines] brush_trails",
+                    vec![
+                        layouts.empty.clone(),
+                        layouts.array_tex.clone(),
+                        layouts.empty.clone(),
+                        layouts.brush.clone(),
+                        layouts.options.clone(),
+                    ],
+                    shader.clone(),
+                    shader_defs(),
+                    "brush_trails_fragment",
+                    trail_format.texture_format(),
+                ));
+
+            let brush_nutrients =
+                pipeline_cache.queue_compute_pipeline(ComputePipelineDescriptor {
+                    label: Some("[SimulationPipelines] brush_nutrients".into()),
+                    layout: vec![
+                        layouts.empty.clone(),
+                        layouts.array_tex.clone(),
+                        layouts.nutrients.clone(),
+                        layouts.brush.clone(),
+                        layouts.options.clone(),
+                    ],
+                    push_constant_ranges: Vec::new(),
+                    shader: shader.clone(),
+                    shader_defs: shader_defs(),
+                    entry_point: "brush_nutrients".into(),
+                });
+
+            // the lifecycle passes of the species with a lifecycle, which don't run any hooks
+            let [tally, scan_tallies, compact, census] =
+                ["tally", "scan_tallies", "compact", "census"].map(|entry_point| {
+                    pipeline_cache.queue_compute_pipeline(ComputePipelineDescriptor {
+                        label: Some(format!("[SimulationPipelines] {}", entry_point).into()),
+                        layout: vec![
+                            layouts.species.clone(),
+                            layouts.empty.clone(),
+                            layouts.empty.clone(),
+                            layouts.seed.clone(),
+                            layouts.options.clone(),
+                        ],
+                        push_constant_ranges: Vec::new(),
+                        shader: shader.clone(),
+                        shader_defs: shader_defs(),
+                        entry_point: entry_point.into(),
+                    })
+                });
 
-            commands.insert_resource(Pipelines::Pending {
+            commands.insert_resource(PipelineIds {
                 init,
                 update,
                 project,
                 blur,
-            })
+                resolve,
+                display,
+                brush_trails,
+                brush_nutrients,
+                tally,
+                scan_tallies,
+                compact,
+                census,
+            });
+            commands.insert_resource(Pipelines::Pending);
         }
-        Some(res) => match *res {
-            Pipelines::Pending {
-                init,
-                update,
-                project,
-                blur,
-            } => {
-                if let (Some(init), Some(update), Some(project), Some(blur)) = (
-                    pipeline_cache.get_compute_pipeline(init),
-                    pipeline_cache.get_compute_pipeline(update),
-                    pipeline_cache.get_compute_pipeline(project),
-                    pipeline_cache.get_render_pipeline(blur),
-                ) {
-                    // insert the map!
-                    commands.init_resource::<super::species::AgentsMap>();
-                    // and mark the pipeline as cached so we start rendering
-                    commands.insert_resource(Pipelines::Cached {
-                        init: init.clone(),
-                        update: update.clone(),
-                        project: project.clone(),
-                        blur: blur.clone(),
-                    });
+        Some((pipelines, ids)) => {
+            let report = |error: String| {
+                if pipeline_error.get().as_ref() != Some(&error) {
+                    error!("failed to compile the simulation pipelines: {}", error);
+                    pipeline_error.set(Some(error));
+                }
+            };
+            match *pipelines {
+                Pipelines::Pending => {
+                    if let Some(error) = ids.error(&pipeline_cache) {
+                        report(error.clone());
+                        commands.insert_resource(Pipelines::Failed { error });
+                    } else if let Some(cached) = ids.cached(&pipeline_cache) {
+                        // start extracting agents
+                        commands.init_resource::<species::Agents>();
+                        // and mark the pipeline as cached so we start rendering
+                        commands.insert_resource(cached);
+                    }
                 }
+                Pipelines::Cached { .. } if **hot_reload => {
+                    // keep simulating with the outdated pipelines until the new ones are ready
+                    if ids.queued(&pipeline_cache) {
+                        *reloading = true;
+                    } else if let Some(error) = ids.error(&pipeline_cache) {
+                        report(error);
+                    } else if *reloading {
+                        if let Some(cached) = ids.cached(&pipeline_cache) {
+                            info!("reloaded the simulation pipelines");
+                            pipeline_error.set(None);
+                            commands.insert_resource(cached);
+                            *reloading = false;
+                        }
+                    }
+                }
+                Pipelines::Cached { .. } => { /* pipelines already valid */ }
+                Pipelines::Failed { .. } if **hot_reload => {
+                    if let Some(error) = ids.error(&pipeline_cache) {
+                        report(error);
+                    } else if let Some(cached) = ids.cached(&pipeline_cache) {
+                        info!("reloaded the simulation pipelines");
+                        pipeline_error.set(None);
+                        commands.init_resource::<species::Agents>();
+                        commands.insert_resource(cached);
+                    }
+                }
+                Pipelines::Failed { .. } => { /* nothing to simulate */ }
             }
-            Pipelines::Cached { .. } => { /* pipelines already valid */ }
-        },
+        }
     }
 }
 
@@ -239,13 +539,27 @@ impl render_graph::Node for Simulation {
         render_context: &mut bevy::render::renderer::RenderContext,
         world: &World,
     ) -> Result<(), render_graph::NodeRunError> {
-        if let Some(Pipelines::Cached {
-            init,
-            update,
-            project,
-            blur,
-        }) = world.get_resource::<Pipelines>()
-        {
+        // the seeds are prepared starting the frame after the pipelines are cached
+        if let (
+            Some(Pipelines::Cached {
+                init,
+                update,
+                project,
+                blur,
+                resolve,
+                display,
+                brush_trails,
+                brush_nutrients,
+                tally,
+                scan_tallies,
+                compact,
+                census,
+            }),
+            Some(seed_bg),
+        ) = (
+            world.get_resource::<Pipelines>(),
+            world.get_resource::<seed::BindGroup>(),
+        ) {
             // pipelines are created & cached
             // let's draw this thing!
 
@@ -254,114 +568,336 @@ impl render_graph::Node for Simulation {
                 secondary: tex_secondary_bg,
             } = world.resource();
 
-            let storage_tex_bg: &trail::StorageTextureBindGroup = world.resource();
-            let seed_bg: &seed::BindGroup = world.resource();
+            let array_tex_bg: &trail::ArrayTextureBindGroup = world.resource();
+            let deposit_bg: &trail::DepositBindGroup = world.resource();
+            let nutrient_bg: &food::NutrientBindGroup = world.resource();
             let empty_bg: &EmptyBindGroup = world.resource();
             let options_bg: &options::BindGroup = world.resource();
+            // the bind group is queued starting the frame after the agents are extracted
+            let species_bg = world.get_resource::<species::BindGroup>();
+            let batches: &species::Batches = world.resource();
+            let agents: &species::Agents = world.resource();
+            let populations: &lifecycle::PopulationBuffer = world.resource();
+            let indirect: &lifecycle::IndirectBuffer = world.resource();
+            let specialized_pipelines: &behavior::SpecializedPipelines = world.resource();
+            // returns the update and project pipelines of a batch, if they're ready
+            let agent_pipelines = |batch: &species::Batch| {
+                if batch.key == behavior::PipelineKey::default() {
+                    Some((update, project))
+                } else {
+                    specialized_pipelines.get(&batch.key)
+                }
+            };
+
+            // initialize new species before their first step
+            for (e, agents, spawn_bg) in world.iter_entities().filter_map(|e| {
+                Some((
+                    e,
+                    e.get::<species::Uninitialized>()?,
+                    e.get::<species::SpawnBindGroup>()?,
+                ))
+            }) {
+                let Some(species_bg) = species_bg else {
+                    break;
+                };
+                let mut pass =
+                    render_context
+                        .command_encoder()
+                        .begin_compute_pass(&ComputePassDescriptor {
+                            label: Some(&format!("init (species): {:?}", e.id())),
+                        });
+                pass.set_bind_group(0, species_bg, &[species::Batches::ALL_AGENTS]);
+                pass.set_bind_group(1, spawn_bg, &[]);
+                pass.set_bind_group(2, empty_bg, &[]);
+                pass.set_bind_group(3, seed_bg.bind_group(), &[seed_bg.offset(0)]);
+                pass.set_bind_group(4, options_bg, &[]);
+                pass.set_pipeline(init);
+                // one invocation per agent
+                pass.dispatch_workgroups(agents.div_ceil(WORKGROUP_SIZE), 1, 1);
+            }
 
-            let species: Vec<_> = world
-                .iter_entities()
-                .filter_map(|e| {
-                    e.get::<species::BindGroup>()
-                        .map(|species_bg| (e, species_bg))
-                })
-                .collect();
+            let gpu_images: &RenderAssets<Image> = world.resource();
+            let DisplayImage(display_image) = world.resource();
+            let trail::LayerViews {
+                primary: primary_views,
+                secondary: secondary_views,
+            } = world.resource();
 
-            {
-                for (e, species_bg) in species {
-                    let id = e.id();
+            let blur::DirectionBindGroups {
+                horizontal,
+                vertical,
+            } = &world.resource::<_>();
 
-                    let encoder = render_context.command_encoder();
-                    {
-                        let mut pass = encoder.begin_compute_pass(&ComputePassDescriptor {
-                            label: Some(&format!("update (species): {:?}", id)),
+            // a new seed restarts the run from empty trails, and the next species in a vacated
+            // layer starts afresh
+            for (layer, view) in primary_views.iter().enumerate() {
+                if seed_bg.restart() || batches.vacated[layer] {
+                    render_context.begin_tracked_render_pass(RenderPassDescriptor {
+                        label: Some("clear trails"),
+                        color_attachments: &[Some(RenderPassColorAttachment {
+                            view,
+                            resolve_target: None,
+                            ops: Operations {
+                                load: LoadOp::Clear(Color::NONE.into()),
+                                store: true,
+                            },
+                        })],
+                        depth_stencil_attachment: None,
+                    });
+                }
+            }
+            if seed_bg.restart() {
+                deposit_bg.clear(render_context.command_encoder());
+            }
+
+            // apply the strokes painted this frame before the agents move
+            let pending: &brush::PendingStrokes = world.resource();
+            let brush_bgs: &brush::BrushBindGroups = world.resource();
+            let resolution: &Resolution = world.resource();
+            if pending.nutrients {
+                let mut pass =
+                    render_context
+                        .command_encoder()
+                        .begin_compute_pass(&ComputePassDescriptor {
+                            label: Some("brush (nutrients)"),
                         });
+                pass.set_bind_group(0, empty_bg, &[]);
+                pass.set_bind_group(1, array_tex_bg, &[]);
+                pass.set_bind_group(2, nutrient_bg, &[]);
+                pass.set_bind_group(3, &brush_bgs[0], &[]);
+                pass.set_bind_group(4, options_bg, &[]);
+                pass.set_pipeline(brush_nutrients);
+                // one invocation per texel
+                pass.dispatch_workgroups(
+                    (resolution.x * resolution.y).div_ceil(WORKGROUP_SIZE),
+                    1,
+                    1,
+                );
+            }
+            let Framebuffers([primary, secondary]) = world.resource();
+            for layer in (0..trail::LAYERS).filter(|&layer| pending.layers[layer]) {
+                // paint into the secondary framebuffer, since the pass reads the primary one
+                {
+                    let mut pass = render_context.begin_tracked_render_pass(RenderPassDescriptor {
+                        label: Some(&format!("brush (trails): layer {}", layer)),
+                        color_attachments: &[Some(RenderPassColorAttachment {
+                            view: &secondary_views[layer],
+                            resolve_target: None,
+                            ops: Operations {
+                                load: LoadOp::Clear(Color::RED.into()),
+                                store: true,
+                            },
+                        })],
+                        depth_stencil_attachment: None,
+                    });
+                    pass.set_bind_group(0, empty_bg, &[]);
+                    pass.set_bind_group(1, array_tex_bg, &[]);
+                    pass.set_bind_group(2, empty_bg, &[]);
+                    pass.set_bind_group(3, &brush_bgs[layer], &[]);
+                    pass.set_bind_group(4, options_bg, &[]);
+                    pass.set_render_pipeline(brush_trails);
+                    pass.draw(0..4, 0..1);
+                }
+                // and copy the painted layer back
+                let layer_origin = Origin3d {
+                    x: 0,
+                    y: 0,
+                    z: layer as u32,
+                };
+                render_context.command_encoder().copy_texture_to_texture(
+                    ImageCopyTexture {
+                        texture: &gpu_images[secondary].texture,
+                        mip_level: 0,
+                        origin: layer_origin,
+                        aspect: TextureAspect::All,
+                    },
+                    ImageCopyTexture {
+                        texture: &gpu_images[primary].texture,
+                        mip_level: 0,
+                        origin: layer_origin,
+                        aspect: TextureAspect::All,
+                    },
+                    Extent3d {
+                        width: resolution.x,
+                        height: resolution.y,
+                        depth_or_array_layers: 1,
+                    },
+                );
+            }
 
-                        pass.set_bind_group(0, species_bg, &[]);
-                        pass.set_bind_group(1, tex_primary_bg, &[]);
+            // the populations may have been written since the last frame
+            indirect.copy(render_context.command_encoder(), populations);
+            for step in 0..**world.resource::<time::Steps>() {
+                // move every agent before any trails change, so that all species sense the same map
+                if let Some(species_bg) = species_bg {
+                    let mut pass = render_context.command_encoder().begin_compute_pass(
+                        &ComputePassDescriptor {
+                            label: Some("update"),
+                        },
+                    );
+                    pass.set_bind_group(1, array_tex_bg, &[]);
+                    pass.set_bind_group(2, nutrient_bg, &[]);
+                    pass.set_bind_group(3, seed_bg.bind_group(), &[seed_bg.offset(step)]);
+                    pass.set_bind_group(4, options_bg, &[]);
+                    for batch in &batches.update {
+                        let Some((update, _)) = agent_pipelines(batch) else {
+                            continue;
+                        };
+                        pass.set_bind_group(0, species_bg, &[batch.offset]);
+                        pass.set_pipeline(update);
+                        batch.dispatch(&mut pass, indirect);
+                    }
+                }
+
+                // let the agents of species with a lifecycle die and reproduce
+                let lifecycle_batches = || {
+                    batches
+                        .update
+                        .iter()
+                        .filter(|batch| batch.population.is_some())
+                };
+                if let (Some(species_bg), true) = (species_bg, lifecycle_batches().next().is_some())
+                {
+                    {
+                        let mut pass = render_context.command_encoder().begin_compute_pass(
+                            &ComputePassDescriptor {
+                                label: Some("lifecycle"),
+                            },
+                        );
+                        pass.set_bind_group(1, empty_bg, &[]);
                         pass.set_bind_group(2, empty_bg, &[]);
-                        pass.set_bind_group(3, seed_bg, &[]);
+                        pass.set_bind_group(3, seed_bg.bind_group(), &[seed_bg.offset(step)]);
                         pass.set_bind_group(4, options_bg, &[]);
+                        // count the survivors and offspring of each workgroup, sum the counts
+                        // before each workgroup, and pack the agents in order of their parents
+                        pass.set_pipeline(tally);
+                        for batch in lifecycle_batches() {
+                            pass.set_bind_group(0, species_bg, &[batch.offset]);
+                            batch.dispatch(&mut pass, indirect);
+                        }
+                        pass.set_pipeline(scan_tallies);
+                        for batch in lifecycle_batches() {
+                            pass.set_bind_group(0, species_bg, &[batch.offset]);
+                            pass.dispatch_workgroups(1, 1, 1);
+                        }
+                        pass.set_pipeline(compact);
+                        for batch in lifecycle_batches() {
+                            pass.set_bind_group(0, species_bg, &[batch.offset]);
+                            batch.dispatch(&mut pass, indirect);
+                        }
+                        // count the packed agents of every species
+                        pass.set_bind_group(0, species_bg, &[species::Batches::ALL_AGENTS]);
+                        pass.set_pipeline(census);
+                        pass.dispatch_workgroups(1, 1, 1);
+                    }
+                    for batch in lifecycle_batches() {
+                        agents.copy_next(render_context.command_encoder(), batch);
+                    }
+                    indirect.copy(render_context.command_encoder(), populations);
+                }
 
-                        if e.contains::<species::Uninitialized>() {
-                            // initialize agents
-                            pass.set_bind_group(1, empty_bg, &[]);
-                            pass.set_pipeline(init);
-                        } else {
-                            // update agents
-                            pass.set_bind_group(1, tex_primary_bg, &[]);
-                            pass.set_pipeline(update);
+                // the deposit buffer holds a single layer, so deposit and blur one layer at a time,
+                // skipping the layers without species (or strokes painted this frame)
+                for layer in (0..trail::LAYERS)
+                    .filter(|&layer| batches.occupied[layer] || pending.layers[layer])
+                {
+                    if let Some(species_bg) = species_bg {
+                        let mut pass = render_context.command_encoder().begin_compute_pass(
+                            &ComputePassDescriptor {
+                                label: Some(&format!("project: layer {}", layer)),
+                            },
+                        );
+                        // accumulate the deposits of the agents in this layer
+                        pass.set_bind_group(1, array_tex_bg, &[]);
+                        pass.set_bind_group(2, deposit_bg, &[]);
+                        pass.set_bind_group(3, seed_bg.bind_group(), &[seed_bg.offset(step)]);
+                        pass.set_bind_group(4, options_bg, &[]);
+                        for batch in &batches.project[layer] {
+                            let Some((_, project)) = agent_pipelines(batch) else {
+                                continue;
+                            };
+                            pass.set_bind_group(0, species_bg, &[batch.offset]);
+                            pass.set_pipeline(project);
+                            batch.dispatch(&mut pass, indirect);
                         }
-                        pass.dispatch_workgroups(WORKGROUPS.x, WORKGROUPS.y, WORKGROUPS.z);
                     }
+
+                    // horizontal blur pass
                     {
-                        // @todo maybe need to change shader to blend?
-                        let mut pass = encoder.begin_compute_pass(&ComputePassDescriptor {
-                            label: Some(&format!("project (species): {:?}", id)),
-                        });
-                        // project the agents onto the primary buffer
-                        pass.set_bind_group(0, species_bg, &[]);
-                        pass.set_bind_group(1, empty_bg, &[]);
-                        pass.set_bind_group(2, storage_tex_bg, &[]);
-                        pass.set_bind_group(3, empty_bg, &[]);
+                        let mut pass =
+                            render_context.begin_tracked_render_pass(RenderPassDescriptor {
+                                label: Some(&format!("blur (horizontal): layer {}", layer)),
+                                color_attachments: &[Some(RenderPassColorAttachment {
+                                    view: &secondary_views[layer],
+                                    resolve_target: None,
+                                    ops: Operations {
+                                        load: LoadOp::Clear(Color::RED.into()),
+                                        store: true,
+                                    },
+                                })],
+                                depth_stencil_attachment: None,
+                            });
+                        pass.set_bind_group(0, empty_bg, &[]);
+                        pass.set_bind_group(1, &tex_primary_bg[layer], &[]);
+                        pass.set_bind_group(2, deposit_bg, &[]);
+                        pass.set_bind_group(3, horizontal, &[]);
                         pass.set_bind_group(4, options_bg, &[]);
-                        pass.set_pipeline(project);
-                        pass.dispatch_workgroups(WORKGROUPS.x, WORKGROUPS.y, WORKGROUPS.z);
+                        pass.set_render_pipeline(blur);
+                        pass.draw(0..4, 0..1);
+                    }
+
+                    // vertical blur pass, which also resolves the deposits into the trail map
+                    {
+                        let mut pass =
+                            render_context.begin_tracked_render_pass(RenderPassDescriptor {
+                                label: Some(&format!("blur (vertical): layer {}", layer)),
+                                color_attachments: &[Some(RenderPassColorAttachment {
+                                    view: &primary_views[layer],
+                                    resolve_target: None,
+                                    ops: Operations {
+                                        load: LoadOp::Clear(Color::BLUE.into()),
+                                        store: true,
+                                    },
+                                })],
+                                depth_stencil_attachment: None,
+                            });
+                        pass.set_bind_group(0, empty_bg, &[]);
+                        pass.set_bind_group(1, &tex_secondary_bg[layer], &[]);
+                        pass.set_bind_group(2, deposit_bg, &[]);
+                        pass.set_bind_group(3, vertical, &[]);
+                        pass.set_bind_group(4, options_bg, &[]);
+                        pass.set_render_pipeline(resolve);
+                        pass.draw(0..4, 0..1);
                     }
                 }
             }
 
-            let gpu_images: &RenderAssets<Image> = world.resource();
-            let Framebuffers([fb_primary, fb_secondary]): &Framebuffers = world.resource();
-
-            let blur::DirectionBindGroups {
-                horizontal,
-                vertical,
-            } = &world.resource::<_>();
-
-            // horizontal blur pass
-            {
-                let mut pass = render_context.begin_tracked_render_pass(RenderPassDescriptor {
-                    label: "blur (horizontal)".into(),
-                    color_attachments: &[Some(RenderPassColorAttachment {
-                        view: &gpu_images[fb_secondary].texture_view,
-                        resolve_target: None,
-                        ops: Operations {
-                            load: LoadOp::Clear(Color::RED.into()),
-                            store: true,
-                        },
-                    })],
-                    depth_stencil_attachment: None,
-                });
-                pass.set_bind_group(0, empty_bg, &[]);
-                pass.set_bind_group(1, tex_primary_bg, &[]);
-                pass.set_bind_group(2, empty_bg, &[]);
-                pass.set_bind_group(3, horizontal, &[]);
-                pass.set_bind_group(4, options_bg, &[]);
-                pass.set_render_pipeline(blur);
-                pass.draw(0..4, 0..1);
-            }
+            // count the live agents of the species with a lifecycle
+            world
+                .resource::<lifecycle::Readback>()
+                .copy(render_context.command_encoder(), populations);
 
-            // vertical blur pass
+            // colorize the trails for display
             {
+                let palette_bg: &display::BindGroup = world.resource();
                 let mut pass = render_context.begin_tracked_render_pass(RenderPassDescriptor {
-                    label: "blur (vertical)".into(),
+                    label: "display".into(),
                     color_attachments: &[Some(RenderPassColorAttachment {
-                        view: &gpu_images[fb_primary].texture_view,
+                        view: &gpu_images[display_image].texture_view,
                         resolve_target: None,
                         ops: Operations {
-                            load: LoadOp::Clear(Color::BLUE.into()),
+                            load: LoadOp::Clear(Color::BLACK.into()),
                             store: true,
                         },
                     })],
                     depth_stencil_attachment: None,
                 });
                 pass.set_bind_group(0, empty_bg, &[]);
-                pass.set_bind_group(1, tex_secondary_bg, &[]);
+                pass.set_bind_group(1, array_tex_bg, &[]);
                 pass.set_bind_group(2, empty_bg, &[]);
-                pass.set_bind_group(3, vertical, &[]);
+                pass.set_bind_group(3, palette_bg, &[]);
                 pass.set_bind_group(4, options_bg, &[]);
-                pass.set_render_pipeline(blur);
+                pass.set_render_pipeline(display);
                 pass.draw(0..4, 0..1);
             }
         }
@@ -373,15 +909,28 @@ pub struct Plugin;
 
 impl bevy::app::Plugin for Plugin {
     fn build(&self, app: &mut App) {
-        app.add_plugin(blur::Plugin)
+        app.add_plugin(behavior::Plugin)
+            .add_plugin(blur::Plugin)
+            .add_plugin(brush::Plugin)
             .add_plugin(species::Plugin)
+            .add_plugin(display::Plugin)
             .add_plugin(seed::Plugin)
+            .add_plugin(time::Plugin)
             .add_plugin(trail::Plugin)
+            // the options bind group binds the food sources
+            .add_plugin(food::Plugin)
+            .add_plugin(lifecycle::Plugin)
             .add_plugin(options::Plugin);
+        let pipeline_error = PipelineError::default();
+        app.add_plugin(ExtractResourcePlugin::<HotReload>::default())
+            .init_resource::<HotReload>()
+            .insert_resource(pipeline_error.clone());
         // add render stuff
         {
             let render_app = app.sub_app_mut(RenderApp);
             render_app
+                // shares the error with the main world
+                .insert_resource(pipeline_error)
                 .init_resource::<EmptyBindGroupLayout>()
                 .init_resource::<EmptyBindGroup>()
                 .add_system(render_queue_pipelines.in_set(RenderSet::Queue));
@@ -13,12 +13,53 @@ use bevy::{
 use bytemuck::{Pod, Zeroable};
 use derive_more::From;
 
-#[derive(Resource, From, Clone, Default, ExtractResource)]
+use super::{food, Timestep};
+use crate::Resolution;
+
+#[derive(Resource, From, Clone, ExtractResource)]
 pub struct Options {
-    /// Configures how quickly trails evaporate over time. Should be in [0, 1].
+    /// Trail strength lost per second.
     pub evaporation: f32,
-    // /// Lerp between trail map and blurred map.
-    // pub diffusion: f32,
+    /// Determines how agents (and their sensors) treat the edges of the world.
+    pub boundary: BoundaryMode,
+    /// Lerp between trail map and blurred map, applied every step. Should be in [0, 1].
+    pub diffusion: f32,
+    /// Radius of the blur kernel in texels, at most [MAX_KERNEL_RADIUS](crate::MAX_KERNEL_RADIUS).
+    pub kernel_radius: u32,
+    /// Standard deviation of the blur kernel in texels.
+    pub kernel_sigma: f32,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            evaporation: 0.0,
+            boundary: BoundaryMode::default(),
+            diffusion: 1.0,
+            kernel_radius: 3,
+            kernel_sigma: 1.5,
+        }
+    }
+}
+
+/// Topology of the edges of the world.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[repr(u32)]
+pub enum BoundaryMode {
+    /// The world is a torus: agents leaving one edge re-enter on the opposite edge and sensors
+    /// read across the seam, so the resulting trail map tiles seamlessly.
+    Wrap = 0,
+    /// Agents bounce off the edges of the world.
+    #[default]
+    Reflect = 1,
+    /// Agents leaving the world are killed and respawned at a random point with a random heading.
+    Respawn = 2,
+    /// Agents stick to the edges of the world until they steer away from them.
+    Sticky = 3,
+}
+
+impl BoundaryMode {
+    pub const ALL: [BoundaryMode; 4] = [Self::Wrap, Self::Reflect, Self::Respawn, Self::Sticky];
 }
 
 #[derive(Resource, Deref)]
@@ -41,23 +82,35 @@ impl FromWorld for Buffer {
 #[repr(C)]
 struct GpuOptions {
     evaporation: f32,
-    // diffusion: f32,
-    _padding: [u32; 3],
+    boundary: u32,
+    diffusion: f32,
+    /// Simulated seconds per step.
+    timestep: f32,
+    /// The size of the world in units of its height, for converting distances to agent positions.
+    world_size: Vec2,
 }
 
-impl From<Options> for GpuOptions {
-    fn from(value: Options) -> Self {
+impl GpuOptions {
+    fn new(options: &Options, resolution: Resolution, timestep: &Timestep) -> Self {
         Self {
-            evaporation: value.evaporation,
-            // diffusion: value.diffusion,
-            _padding: [0; 3],
+            evaporation: options.evaporation,
+            boundary: options.boundary as u32,
+            diffusion: options.diffusion,
+            timestep: timestep.step,
+            world_size: Vec2::new(resolution.aspect_ratio(), 1.0),
         }
     }
 }
 
-fn prepare_simulation_options(queue: Res<RenderQueue>, buffer: Res<Buffer>, options: Res<Options>) {
-    if options.is_changed() {
-        let options = GpuOptions::from(options.clone());
+fn prepare_simulation_options(
+    queue: Res<RenderQueue>,
+    buffer: Res<Buffer>,
+    options: Res<Options>,
+    resolution: Res<Resolution>,
+    timestep: Res<Timestep>,
+) {
+    if options.is_changed() || resolution.is_changed() || timestep.is_changed() {
+        let options = GpuOptions::new(&options, *resolution, &timestep);
         queue.write_buffer(&buffer, 0, bytemuck::bytes_of(&options))
     }
 }
@@ -70,16 +123,28 @@ impl FromWorld for BindGroupLayout {
         let device: &RenderDevice = world.resource::<RenderDevice>();
         let layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
             label: "OptionsBindGroupLayout".into(),
-            entries: &[BindGroupLayoutEntry {
-                binding: 0,
-                visibility: ShaderStages::COMPUTE | ShaderStages::FRAGMENT,
-                ty: BindingType::Buffer {
-                    ty: BufferBindingType::Uniform,
-                    has_dynamic_offset: false,
-                    min_binding_size: None,
+            entries: &[
+                BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: ShaderStages::COMPUTE | ShaderStages::FRAGMENT,
+                    ty: BindingType::Buffer {
+                        ty: BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
                 },
-                count: None,
-            }],
+                BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: ShaderStages::COMPUTE,
+                    ty: BindingType::Buffer {
+                        ty: BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: Some((food::Buffer::SIZE as u64).try_into().unwrap()),
+                    },
+                    count: None,
+                },
+            ],
         });
         Self(layout)
     }
@@ -93,13 +158,20 @@ impl FromWorld for BindGroup {
         let device: &RenderDevice = world.resource();
         let layout: &BindGroupLayout = world.resource();
         let buffer: &Buffer = world.resource();
+        let food: &food::Buffer = world.resource();
         let bind_group = device.create_bind_group(&BindGroupDescriptor {
             label: "options::BindGroup".into(),
             layout,
-            entries: &[BindGroupEntry {
-                binding: 0,
-                resource: buffer.as_entire_binding(),
-            }],
+            entries: &[
+                BindGroupEntry {
+                    binding: 0,
+                    resource: buffer.as_entire_binding(),
+                },
+                BindGroupEntry {
+                    binding: 1,
+                    resource: food.as_entire_binding(),
+                },
+            ],
         });
         Self(bind_group)
     }
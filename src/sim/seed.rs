@@ -1,34 +1,133 @@
+use std::sync::{
+    atomic::{AtomicU64, Ordering},
+    Arc,
+};
+
 use bevy::{
     prelude::*,
     render::{
+        extract_resource::{ExtractResource, ExtractResourcePlugin},
         render_resource::{
             BindGroupDescriptor, BindGroupEntry, BindGroupLayoutDescriptor, BindGroupLayoutEntry,
-            BindingType, BufferBindingType, BufferInitDescriptor, BufferUsages, ShaderStages,
+            BindingType, BufferBindingType, BufferId, DynamicUniformBuffer, ShaderStages,
+            ShaderType,
         },
         renderer::{RenderDevice, RenderQueue},
         RenderApp, RenderSet,
     },
 };
+use derive_more::From;
 
-#[derive(Resource, Deref, DerefMut)]
-pub(crate) struct Buffer(bevy::render::render_resource::Buffer);
+use super::{
+    species::{NumAgents, Reinitialize},
+    time::Steps,
+    Pipelines,
+};
 
-impl FromWorld for Buffer {
-    fn from_world(world: &mut World) -> Self {
-        let device: &RenderDevice = world.resource();
-        let seed: u32 = rand::random();
-        let buffer = device.create_buffer_with_data(&BufferInitDescriptor {
-            label: "random seed (u32)".into(),
-            contents: bytemuck::bytes_of(&seed),
-            usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST,
-        });
-        Self(buffer)
+/// Seeds every random decision the simulation makes. Runs started with the same seed, options and
+/// species produce identical agents after the same number of simulated steps.
+///
+/// Defaults to a random seed. Changing the seed restarts the run: the [SimulationStep] counter is
+/// reset, every species is [reinitialized](Reinitialize) and the trails are cleared.
+#[derive(Resource, Clone, Copy, Debug, PartialEq, Eq, Deref, DerefMut, From, ExtractResource)]
+pub struct SimulationSeed(pub u64);
+
+impl Default for SimulationSeed {
+    fn default() -> Self {
+        Self(rand::random())
     }
 }
 
-fn render_prepare_update_random_seed(queue: Res<RenderQueue>, buffer: Res<Buffer>) {
-    let seed: u32 = rand::random();
-    queue.write_buffer(&buffer, 0, bytemuck::bytes_of(&seed));
+/// Counts the steps simulated since the [SimulationSeed] was last set (see
+/// [Timestep](super::Timestep)). Frames rendered before the pipelines finish compiling are not
+/// counted.
+#[derive(Resource, Clone, Default)]
+pub struct SimulationStep(Arc<AtomicU64>);
+
+impl SimulationStep {
+    /// Returns the number of steps simulated so far.
+    pub fn get(&self) -> u64 {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// Derives the GPU seed for a step from the simulation seed (splitmix64).
+fn step_seed(seed: u64, step: u64) -> u32 {
+    let mut z = seed.wrapping_add(step.wrapping_add(1).wrapping_mul(0x9E37_79B9_7F4A_7C15));
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    (z ^ (z >> 31)) as u32
+}
+
+/// Respawns every species when the seed changes, so that the run restarts from the new seed.
+fn reinitialize_on_new_seed(
+    mut commands: Commands,
+    seed: Res<SimulationSeed>,
+    species: Query<Entity, With<NumAgents>>,
+) {
+    if !seed.is_changed() || seed.is_added() {
+        return;
+    }
+    for id in &species {
+        commands.entity(id).insert(Reinitialize);
+    }
+}
+
+#[derive(Resource, Deref, DerefMut, Default)]
+/// The seed of each step simulated during this frame.
+struct Buffer(DynamicUniformBuffer<u32>);
+
+#[allow(clippy::too_many_arguments)]
+fn render_prepare_update_random_seed(
+    mut commands: Commands,
+    device: Res<RenderDevice>,
+    queue: Res<RenderQueue>,
+    layout: Res<BindGroupLayout>,
+    mut buffer: ResMut<Buffer>,
+    seed: Res<SimulationSeed>,
+    step: Res<SimulationStep>,
+    steps: Res<Steps>,
+    pipelines: Option<Res<Pipelines>>,
+    bind_group: Option<ResMut<BindGroup>>,
+    // the buffer the bind group was created for
+    mut bound: Local<Option<BufferId>>,
+) {
+    if seed.is_changed() {
+        step.0.store(0, Ordering::Relaxed);
+    }
+    if !pipelines.is_some_and(|pipelines| pipelines.loaded()) {
+        // nothing is simulated this frame
+        return;
+    }
+    let first = step.0.fetch_add(**steps as u64, Ordering::Relaxed);
+    buffer.clear();
+    // initialization uses the seed of the next step, even when no step is due this frame
+    let offsets = (first..first + (**steps).max(1) as u64)
+        .map(|step| buffer.push(step_seed(**seed, step)))
+        .collect();
+    buffer.write_buffer(&device, &queue);
+
+    // the buffer is only reallocated when it grows
+    let buffer_id = buffer.buffer().map(|buffer| buffer.id());
+    if let (Some(mut bind_group), true) = (bind_group, *bound == buffer_id) {
+        bind_group.offsets = offsets;
+        bind_group.restart = seed.is_changed();
+        return;
+    }
+    *bound = buffer_id;
+    let bind_group = device.create_bind_group(&BindGroupDescriptor {
+        label: "seed::BindGroup".into(),
+        layout: &layout,
+        entries: &[BindGroupEntry {
+            binding: 0,
+            resource: buffer.binding().unwrap(),
+        }],
+    });
+    commands.insert_resource(BindGroup {
+        bind_group,
+        offsets,
+        restart: seed.is_changed(),
+    });
 }
 
 #[derive(Resource, Deref, DerefMut)]
@@ -44,8 +143,8 @@ impl FromWorld for BindGroupLayout {
                 visibility: ShaderStages::COMPUTE | ShaderStages::FRAGMENT,
                 ty: BindingType::Buffer {
                     ty: BufferBindingType::Uniform,
-                    has_dynamic_offset: false,
-                    min_binding_size: Some((std::mem::size_of::<u32>() as u64).try_into().unwrap()),
+                    has_dynamic_offset: true,
+                    min_binding_size: Some(u32::min_size()),
                 },
                 count: None,
             }],
@@ -54,23 +153,28 @@ impl FromWorld for BindGroupLayout {
     }
 }
 
-#[derive(Resource, Deref, DerefMut)]
-pub(crate) struct BindGroup(bevy::render::render_resource::BindGroup);
+#[derive(Resource)]
+pub(crate) struct BindGroup {
+    bind_group: bevy::render::render_resource::BindGroup,
+    offsets: Vec<u32>,
+    /// Whether the seed changed this frame.
+    restart: bool,
+}
 
-impl FromWorld for BindGroup {
-    fn from_world(world: &mut World) -> Self {
-        let device: &RenderDevice = world.resource();
-        let layout: &BindGroupLayout = world.resource();
-        let buffer: &Buffer = world.resource();
-        let bind_group = device.create_bind_group(&BindGroupDescriptor {
-            label: "seed::BindGroup".into(),
-            layout,
-            entries: &[BindGroupEntry {
-                binding: 0,
-                resource: buffer.as_entire_binding(),
-            }],
-        });
-        Self(bind_group)
+impl BindGroup {
+    pub(crate) fn bind_group(&self) -> &bevy::render::render_resource::BindGroup {
+        &self.bind_group
+    }
+
+    /// Whether the run restarts this frame, in which case the trails are cleared before the first
+    /// step.
+    pub(crate) fn restart(&self) -> bool {
+        self.restart
+    }
+
+    /// The dynamic offset selecting the seed of the `step`th step of this frame.
+    pub(crate) fn offset(&self, step: u32) -> u32 {
+        self.offsets[step as usize]
     }
 }
 
@@ -78,10 +182,17 @@ pub(crate) struct Plugin;
 
 impl bevy::app::Plugin for Plugin {
     fn build(&self, app: &mut App) {
+        let step = SimulationStep::default();
+        app.add_plugin(ExtractResourcePlugin::<SimulationSeed>::default())
+            .init_resource::<SimulationSeed>()
+            .insert_resource(step.clone())
+            .add_system(reinitialize_on_new_seed);
+
         app.sub_app_mut(RenderApp)
+            // shares the counter with the main world
+            .insert_resource(step)
             .init_resource::<Buffer>()
             .init_resource::<BindGroupLayout>()
-            .init_resource::<BindGroup>()
             .add_system(render_prepare_update_random_seed.in_set(RenderSet::Prepare));
     }
 }
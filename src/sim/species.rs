@@ -1,13 +1,23 @@
-use std::f32::consts::FRAC_PI_6;
+use std::{
+    f32::consts::FRAC_PI_6,
+    ops::Range,
+    sync::{Arc, Mutex},
+};
 
 use bevy::{
+    ecs::system::SystemParam,
     prelude::*,
     render::{
+        render_asset::RenderAssets,
         render_resource::{
             BindGroupDescriptor, BindGroupEntry, BindGroupLayoutDescriptor, BindGroupLayoutEntry,
-            BindingType, Buffer, BufferBindingType, BufferDescriptor, BufferUsages, ShaderStages,
+            BindingResource, BindingType, Buffer, BufferBindingType, BufferDescriptor, BufferSize,
+            BufferUsages, CommandEncoder, CommandEncoderDescriptor, ComputePass,
+            DynamicUniformBuffer, ShaderStages, ShaderType, TextureId, TextureSampleType,
+            TextureViewDimension,
         },
         renderer::{RenderDevice, RenderQueue},
+        texture::FallbackImage,
         Extract, RenderApp, RenderSet,
     },
     utils::HashMap,
@@ -15,32 +25,197 @@ use bevy::{
 use bytemuck::{Pod, Zeroable};
 use derive_more::From;
 
+use super::{
+    behavior::{self, PipelineKey, SlotPipelines},
+    lifecycle::{GpuPopulation, IndirectBuffer, Lifecycle, PopulationBuffer},
+    trail,
+};
+
+/// The maximum number of species that can interact with one another.
+pub const MAX_SPECIES: usize = 16;
+
+/// The maximum number of sensors per agent.
+pub const MAX_SENSORS: u32 = 16;
+
 #[derive(Bundle)]
 pub struct SpeciesBundle {
     pub num_agents: NumAgents,
     pub qualities: Qualities,
+    pub spawn_pattern: SpawnPattern,
+    pub interactions: Interactions,
 }
 
 #[derive(Deref, Clone, Component, From)]
 pub struct NumAgents(pub u32);
 
+/// The most agents the GPU can hold across all species, if the species ask for more. Species are
+/// then laid out by slot until the limit, and the rest get fewer agents (or none). Shared with the
+/// render world, which sets it.
+#[derive(Resource, Clone, Default)]
+pub struct AgentLimit(Arc<Mutex<Option<u32>>>);
+
+impl AgentLimit {
+    /// Returns the limit, if the species' agents had to be clamped to it.
+    pub fn get(&self) -> Option<u32> {
+        *self.0.lock().unwrap()
+    }
+
+    fn set(&self, limit: Option<u32>) {
+        *self.0.lock().unwrap() = limit;
+    }
+}
+
+/// Rates are per simulated second (see [Timestep](super::Timestep)).
 #[derive(Component, Clone)]
 pub struct Qualities {
     pub color: Color,
+    /// Units (world heights) per second.
     pub speed: f32,
-    pub turn_speed: f32,
-    pub view_distance: f32,
-    pub field_of_view: f32,
+    /// Radians between the front sensor and the outermost sensors on either side.
+    pub sensor_angle: f32,
+    /// Units (world heights) between an agent and its sensors.
+    pub sensor_offset: f32,
+    /// The number of sensors, spread evenly over the sensor angle, up to [MAX_SENSORS]. A single
+    /// sensor faces forward.
+    pub sensor_count: u32,
+    /// The width of the square of texels each sensor averages.
+    pub sensor_size: u32,
+    /// Radians per second an agent turns toward an outermost sensor, and proportionally less
+    /// toward the sensors in between.
+    pub rotation_angle: f32,
+    /// How agents pick the sensor to turn toward.
+    pub steering: SteeringMode,
+    /// Radians per square root of a second: the width of the uniformly random perturbation added
+    /// to the heading every step, scaled by the square root of the timestep so that the heading
+    /// diffuses at the same rate per second whatever the step.
+    pub heading_noise: f32,
+    /// How agents move and reorient on their own, on top of steering.
+    pub movement: MovementModel,
+    /// Gives agents momentum, so that they accelerate and turn gradually instead of instantly.
+    /// `None` moves agents at `speed` along their heading.
+    pub inertia: Option<Inertia>,
+    /// Lets agents die and reproduce. `None` keeps the number of agents fixed.
+    pub lifecycle: Option<Lifecycle>,
+    /// Trail strength each agent adds to the texel it occupies per second. Deposits accumulate, so
+    /// denser regions lay down stronger trails.
+    pub deposit: f32,
 }
 
 impl Default for Qualities {
     fn default() -> Self {
         Self {
             color: Color::WHITE,
-            speed: 6e-6,
-            turn_speed: 2.0e-3,
-            view_distance: 1.0e-2,
-            field_of_view: FRAC_PI_6,
+            speed: 3.6e-4,
+            sensor_angle: FRAC_PI_6,
+            sensor_offset: 1.0e-2,
+            sensor_count: 3,
+            sensor_size: 1,
+            rotation_angle: 0.12,
+            steering: SteeringMode::default(),
+            // 0.1 degrees per step at 60 steps per second
+            heading_noise: 1.351_91e-2,
+            movement: MovementModel::default(),
+            inertia: None,
+            lifecycle: None,
+            deposit: 60.0,
+        }
+    }
+}
+
+/// How agents pick the sensor to turn toward. Each mode compiles its own pipelines.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum SteeringMode {
+    /// Turn toward the sensor that senses the most, even if it only senses repellent. Sensors that
+    /// tie split the difference.
+    #[default]
+    Argmax,
+    /// The rules of Jones' Physarum model, using the middle and outermost sensors: keep going
+    /// straight when the middle sensor senses the most, turn toward a random side when both sides
+    /// sense more than the middle, and otherwise turn toward the side that senses more.
+    Jones,
+    /// Turn toward a random sensor, with probability proportional to what it senses. Sensors that
+    /// sense nothing (or repellent) are never picked.
+    WeightedRandom,
+    /// Turn toward a random sensor, with probability proportional to `exp(sensed / temperature)`.
+    /// Low temperatures approach [SteeringMode::Argmax], and high ones turn at random.
+    Softmax { temperature: f32 },
+}
+
+impl SteeringMode {
+    /// The shader def selecting the mode, if it isn't the default.
+    pub(crate) fn shader_def(self) -> Option<&'static str> {
+        match self {
+            Self::Argmax => None,
+            Self::Jones => Some("STEERING_JONES"),
+            Self::WeightedRandom => Some("STEERING_WEIGHTED_RANDOM"),
+            Self::Softmax { .. } => Some("STEERING_SOFTMAX"),
+        }
+    }
+
+    fn temperature(self) -> f32 {
+        match self {
+            Self::Softmax { temperature } => temperature,
+            _ => 0.0,
+        }
+    }
+}
+
+/// How agents move and reorient on their own, on top of steering. Each model compiles its own
+/// pipelines. Set the rotation angle to zero for agents that only follow their movement model.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum MovementModel {
+    /// Keep moving along the heading, which only steering and the heading noise change.
+    #[default]
+    Constant,
+    /// A Lévy walk: turn to a random heading after straight runs whose lengths (in units) follow
+    /// the power law `length^-exponent`. Exponents between 1 and 3 range from rare, very long runs
+    /// to short ones; around 2 searches sparse food best.
+    Levy { exponent: f32, min_run: f32 },
+    /// Bacterial chemotaxis: tumble to a random heading `tumble_rate` times per second on
+    /// average, and less often while what the agent senses increases along its heading (or more
+    /// often while it decreases). The agent compares what it senses at its position and at the
+    /// sensor offset ahead, weighted by `sensitivity`.
+    RunAndTumble { tumble_rate: f32, sensitivity: f32 },
+    /// The heading diffuses, so that agents forget it over `persistence_length` units on average.
+    CorrelatedRandomWalk { persistence_length: f32 },
+}
+
+impl MovementModel {
+    /// The shader def selecting the model, if it isn't the default.
+    pub(crate) fn shader_def(self) -> Option<&'static str> {
+        match self {
+            Self::Constant => None,
+            Self::Levy { .. } => Some("MOVEMENT_LEVY"),
+            Self::RunAndTumble { .. } => Some("MOVEMENT_RUN_AND_TUMBLE"),
+            Self::CorrelatedRandomWalk { .. } => Some("MOVEMENT_CORRELATED_RANDOM_WALK"),
+        }
+    }
+}
+
+/// Moves agents by their velocity, which forces change over time. Drag pulls the velocity toward
+/// cruising at the species' speed along the steered heading, and the trail under an agent slows it
+/// down. Species with inertia compile their own pipelines, and behavior modules that replace
+/// [Hook::Move](super::Hook::Move) ignore it.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Inertia {
+    /// How strongly agents resist changes to their velocity. Agents reach their cruising velocity
+    /// within about `mass / drag` seconds.
+    pub mass: f32,
+    /// The drag (per second) that pulls the velocity toward the cruising velocity.
+    pub drag: f32,
+    /// Additional drag per unit of trail (and nutrient) an agent senses where it is.
+    pub trail_drag: f32,
+    /// Units (world heights) per second agents can't exceed.
+    pub max_speed: f32,
+}
+
+impl Default for Inertia {
+    fn default() -> Self {
+        Self {
+            mass: 1.0,
+            drag: 10.0,
+            trail_drag: 0.1,
+            max_speed: 1.0e-3,
         }
     }
 }
@@ -50,21 +225,186 @@ impl Default for Qualities {
 pub struct GpuQualities {
     color: Vec3,
     speed: f32,
-    turn_speed: f32,
-    view_distance: f32,
-    field_of_view: f32,
-    _padding: [f32; 5],
+    sensor_angle: f32,
+    sensor_offset: f32,
+    sensor_count: u32,
+    sensor_size: u32,
+    rotation_angle: f32,
+    slot: u32,
+    deposit: f32,
+    temperature: f32,
+    heading_noise: f32,
+    levy_exponent: f32,
+    levy_min_run: f32,
+    tumble_rate: f32,
+    tumble_sensitivity: f32,
+    persistence_length: f32,
+    mass: f32,
+    drag: f32,
+    trail_drag: f32,
+    max_speed: f32,
+    initial_energy: f32,
+    drain: f32,
+    intake: f32,
+    split_energy: f32,
+    _padding: [u32; 2],
 }
 
-impl From<Qualities> for GpuQualities {
-    fn from(qualities: Qualities) -> Self {
-        Self {
+impl GpuQualities {
+    fn new(qualities: Qualities, slot: usize) -> Self {
+        let mut gpu = Self {
             color: Vec4::from_array(qualities.color.as_rgba_f32()).truncate(),
             speed: qualities.speed,
-            turn_speed: qualities.turn_speed,
-            view_distance: qualities.view_distance,
-            field_of_view: qualities.field_of_view,
-            _padding: Default::default(),
+            sensor_angle: qualities.sensor_angle,
+            sensor_offset: qualities.sensor_offset,
+            // at least the front sensor
+            sensor_count: qualities.sensor_count.clamp(1, MAX_SENSORS),
+            sensor_size: qualities.sensor_size.max(1),
+            rotation_angle: qualities.rotation_angle,
+            slot: slot as u32,
+            deposit: qualities.deposit,
+            temperature: qualities.steering.temperature(),
+            heading_noise: qualities.heading_noise,
+            ..Self::movement(qualities.movement)
+        };
+        if let Some(inertia) = qualities.inertia {
+            gpu.mass = inertia.mass;
+            gpu.drag = inertia.drag;
+            gpu.trail_drag = inertia.trail_drag;
+            gpu.max_speed = inertia.max_speed;
+        }
+        if let Some(lifecycle) = qualities.lifecycle {
+            gpu.initial_energy = lifecycle.initial_energy;
+            gpu.drain = lifecycle.drain;
+            gpu.intake = lifecycle.intake;
+            gpu.split_energy = lifecycle.split_energy;
+        }
+        gpu
+    }
+
+    /// Returns the parameters of a movement model, leaving everything else zeroed.
+    fn movement(movement: MovementModel) -> Self {
+        match movement {
+            MovementModel::Constant => Self::default(),
+            MovementModel::Levy { exponent, min_run } => Self {
+                levy_exponent: exponent,
+                levy_min_run: min_run,
+                ..default()
+            },
+            MovementModel::RunAndTumble {
+                tumble_rate,
+                sensitivity,
+            } => Self {
+                tumble_rate,
+                tumble_sensitivity: sensitivity,
+                ..default()
+            },
+            MovementModel::CorrelatedRandomWalk { persistence_length } => Self {
+                persistence_length,
+                ..default()
+            },
+        }
+    }
+}
+
+/// How strongly a species is attracted to (positive weights) or repelled by (negative weights) the
+/// trails of each species, including its own.
+#[derive(Component, Clone, Debug)]
+pub struct Interactions {
+    /// Weight of the species' own trail.
+    pub self_weight: f32,
+    /// Weight of the trails of species missing from `weights`.
+    pub default_weight: f32,
+    /// Weights of the trails of other species, keyed by the other species' entity.
+    pub weights: HashMap<Entity, f32>,
+}
+
+impl Interactions {
+    /// Returns the weight species `this` gives to the trail of species `other`.
+    pub fn weight(&self, this: Entity, other: Entity) -> f32 {
+        if this == other {
+            self.self_weight
+        } else {
+            self.weights
+                .get(&other)
+                .copied()
+                .unwrap_or(self.default_weight)
+        }
+    }
+}
+
+impl Default for Interactions {
+    fn default() -> Self {
+        Self {
+            self_weight: 1.0,
+            default_weight: 0.0,
+            weights: HashMap::default(),
+        }
+    }
+}
+
+/// Determines where a species' agents are placed, and which way they face, when they are
+/// initialized. Changing the pattern reinitializes the species' agents.
+#[derive(Component, Clone, Debug, Default, PartialEq)]
+pub enum SpawnPattern {
+    /// Uniformly within a disc of radius 0.5 about the center of the world, facing the center.
+    #[default]
+    Disc,
+    /// Uniformly over the whole world with random headings.
+    Uniform,
+    /// All agents start at `center`, heading in random directions.
+    Burst { center: Vec2 },
+    /// On a ring of the given radius about the center of the world, facing away from the center.
+    RingOutward { radius: f32 },
+    /// On a ring of the given radius about the center of the world, facing the center.
+    RingInward { radius: f32 },
+    /// Uniformly within a disc of radius 0.5 about the center of the world with random headings.
+    RandomHeading,
+    /// On a regular grid covering the world with random headings.
+    Grid,
+    /// Positions are sampled proportionally to the luminance of the image, with random headings.
+    /// Initialization is deferred until the image has loaded.
+    Image(Handle<Image>),
+}
+
+#[derive(Copy, Clone, Pod, Zeroable, Default)]
+#[repr(C)]
+struct GpuSpawnPattern {
+    kind: u32,
+    radius: f32,
+    center: Vec2,
+    /// The slot new agents are tagged with.
+    slot: u32,
+    /// The index of the species' first agent in the shared agent buffer.
+    start: u32,
+    /// The number of agents of the species.
+    count: u32,
+    /// Agents before this index (relative to `start`) are kept when initializing, so that growing
+    /// a species only spawns the new agents.
+    first_agent: u32,
+}
+
+impl GpuSpawnPattern {
+    fn new(pattern: &SpawnPattern, slot: usize, agents: Range<u32>, first_agent: u32) -> Self {
+        // kinds must match the `SPAWN_*` constants in simulate.wgsl
+        let (kind, radius, center) = match pattern {
+            SpawnPattern::Disc => (0, 0.5, Vec2::splat(0.5)),
+            SpawnPattern::Uniform => (1, 0.0, Vec2::ZERO),
+            SpawnPattern::Burst { center } => (2, 0.0, *center),
+            SpawnPattern::RingOutward { radius } => (3, *radius, Vec2::splat(0.5)),
+            SpawnPattern::RingInward { radius } => (4, *radius, Vec2::splat(0.5)),
+            SpawnPattern::RandomHeading => (5, 0.5, Vec2::splat(0.5)),
+            SpawnPattern::Grid => (6, 0.0, Vec2::ZERO),
+            SpawnPattern::Image(_) => (7, 0.0, Vec2::ZERO),
+        };
+        Self {
+            kind,
+            radius,
+            center,
+            slot: slot as u32,
+            start: agents.start,
+            count: agents.len() as u32,
+            first_agent,
         }
     }
 }
@@ -73,96 +413,508 @@ impl From<Qualities> for GpuQualities {
 #[repr(C)]
 struct GpuAgent {
     pos: Vec2,
+    /// Units (world heights) per second.
+    velocity: Vec2,
     angle: f32,
-    _padding: [u8; 4],
+    /// The slot of the agent's species.
+    species: u32,
+    /// Units moved since the agent last reoriented, for [MovementModel::Levy].
+    run: f32,
+    /// For [Lifecycle].
+    energy: f32,
+}
+
+const AGENT_SIZE: u64 = std::mem::size_of::<GpuAgent>() as u64;
+
+/// A workgroup's survivors and offspring.
+const TALLY_SIZE: u64 = std::mem::size_of::<[u32; 2]>() as u64;
+
+#[derive(Resource, Deref)]
+/// The [Qualities] of every species, indexed by slot.
+struct QualitiesBuffer(Buffer);
+
+impl FromWorld for QualitiesBuffer {
+    fn from_world(world: &mut World) -> Self {
+        let device: &RenderDevice = world.resource();
+        Self(device.create_buffer(&BufferDescriptor {
+            label: "species::QualitiesBuffer".into(),
+            usage: BufferUsages::STORAGE | BufferUsages::COPY_DST,
+            size: (MAX_SPECIES * std::mem::size_of::<GpuQualities>()) as u64,
+            mapped_at_creation: false,
+        }))
+    }
+}
+
+/// One weight per slot, so that a row lines up with the channels of the trail map.
+type InteractionsRow = [f32; MAX_SPECIES];
+
+#[derive(Resource, Deref)]
+/// The interaction matrix: the [Interactions] row of every species, indexed by slot.
+struct InteractionsBuffer(Buffer);
+
+impl FromWorld for InteractionsBuffer {
+    fn from_world(world: &mut World) -> Self {
+        let device: &RenderDevice = world.resource();
+        Self(device.create_buffer(&BufferDescriptor {
+            label: "species::InteractionsBuffer".into(),
+            usage: BufferUsages::STORAGE | BufferUsages::COPY_DST,
+            size: (MAX_SPECIES * std::mem::size_of::<InteractionsRow>()) as u64,
+            mapped_at_creation: false,
+        }))
+    }
 }
 
 #[derive(Component, Deref, Clone)]
-pub struct AgentsBuffer(Buffer);
+pub struct SpawnBuffer(Buffer);
 
 #[derive(Component, Deref, Clone)]
-pub struct QualitiesBuffer(Buffer);
+/// The image a species with [SpawnPattern::Image] samples its initial positions from.
+pub struct SpawnImage(Handle<Image>);
 
 #[derive(Resource, Deref, DerefMut, Default)]
-/// Maps species to the existing agents for the species. Lives in the Render world, but the entitiy IDs are the same!
-pub(crate) struct AgentsMap(HashMap<Entity, AgentsBuffer>);
+struct SpawnMap(HashMap<Entity, SpawnBuffer>);
+
+#[derive(Resource, Default)]
+/// The agents of every species in one buffer, tagged with their species' slot. Agents are ordered
+/// by slot, so the species that share a layer of the trail map are contiguous. Lives in the Render
+/// world, and is inserted once the pipelines are ready.
+pub(crate) struct Agents {
+    buffer: Option<Buffer>,
+    /// Laid out like `buffer`. The `compact` pass packs the agents of species with a [Lifecycle] in
+    /// here.
+    next: Option<Buffer>,
+    /// The survivors and offspring of each workgroup of agents of species with a [Lifecycle],
+    /// which `compact` packs them by.
+    tallies: Option<Buffer>,
+    /// The species in each slot and the range of its agents. The entity IDs are the same as in the
+    /// main world!
+    ranges: [Option<(Entity, Range<u32>)>; MAX_SPECIES],
+    /// Whether the species in each slot has a [Lifecycle], in which case its range spans its
+    /// capacity and only the front of it is alive.
+    lifecycles: [bool; MAX_SPECIES],
+}
+
+impl Agents {
+    /// Splits the agents of the species in `slots` into contiguous runs of species that run the
+    /// same pipelines, along with the first slot of each run.
+    fn runs(
+        &self,
+        pipelines: &SlotPipelines,
+        slots: Range<usize>,
+    ) -> Vec<(PipelineKey, usize, Range<u32>)> {
+        let mut runs: Vec<(PipelineKey, usize, Range<u32>)> = vec![];
+        for slot in slots {
+            let Some((_, agents)) = self.ranges[slot]
+                .as_ref()
+                .filter(|(_, agents)| !agents.is_empty())
+            else {
+                continue;
+            };
+            match runs.last_mut() {
+                // the population of a species with a lifecycle is only known on the GPU, so it
+                // always runs on its own
+                Some((key, first, run))
+                    if *key == pipelines[slot]
+                        && !self.lifecycles[*first]
+                        && !self.lifecycles[slot]
+                        && run.end == agents.start =>
+                {
+                    run.end = agents.end;
+                }
+                _ => runs.push((pipelines[slot].clone(), slot, agents.clone())),
+            }
+        }
+        runs
+    }
+
+    /// Replaces the agents of a batch of a species with a [Lifecycle] with the ones the `compact`
+    /// pass packed.
+    pub(crate) fn copy_next(&self, encoder: &mut CommandEncoder, batch: &Batch) {
+        let (Some(buffer), Some(next)) = (&self.buffer, &self.next) else {
+            return;
+        };
+        let offset = batch.agents.start as u64 * AGENT_SIZE;
+        encoder.copy_buffer_to_buffer(
+            next,
+            offset,
+            buffer,
+            offset,
+            batch.agents.len() as u64 * AGENT_SIZE,
+        );
+    }
+}
+
+/// A contiguous range of agents whose species run the same pipelines, which are simulated by a
+/// single dispatch.
+pub(crate) struct Batch {
+    pub(crate) key: PipelineKey,
+    pub(crate) agents: Range<u32>,
+    /// The slot of the batch's species if it has a [Lifecycle], in which case the batch holds only
+    /// that species and is dispatched indirectly.
+    pub(crate) population: Option<usize>,
+    /// The dynamic offset of [BindGroup] selecting the agents of the batch.
+    pub(crate) offset: u32,
+}
+
+impl Batch {
+    /// Dispatches one invocation per (live) agent of the batch.
+    pub(crate) fn dispatch<'a>(&self, pass: &mut ComputePass<'a>, indirect: &'a IndirectBuffer) {
+        match self.population {
+            Some(slot) => pass.dispatch_workgroups_indirect(indirect, GpuPopulation::offset(slot)),
+            None => pass.dispatch_workgroups(
+                (self.agents.len() as u32).div_ceil(super::WORKGROUP_SIZE),
+                1,
+                1,
+            ),
+        }
+    }
+}
+
+#[derive(Resource, Default)]
+/// The batches each step is simulated in. Lives in the Render world.
+///
+/// When every species runs the default pipelines without a [Lifecycle], a step takes one update
+/// dispatch, and one project dispatch per layer of the trail map that has species. More are needed
+/// when:
+/// - species run different pipelines (see [PipelineKey]), which are specialized at compile time,
+///   so each contiguous run of species sharing a pipeline is its own dispatch;
+/// - a species has a [Lifecycle], whose population is only known on the GPU and is dispatched
+///   indirectly on its own;
+/// - species deposit into several layers, since the deposit buffer holds a single layer so that
+///   it doesn't grow with the number of layers.
+pub(crate) struct Batches {
+    pub(crate) update: Vec<Batch>,
+    /// The batches of the species whose trails are in each layer.
+    pub(crate) project: [Vec<Batch>; trail::LAYERS],
+    /// Whether each layer of the trail map has species with agents. The other layers are neither
+    /// projected nor blurred.
+    pub(crate) occupied: [bool; trail::LAYERS],
+    /// The layers that lost their last species this frame, whose trails are cleared so that the
+    /// next species in them starts afresh.
+    pub(crate) vacated: [bool; trail::LAYERS],
+}
+
+impl Batches {
+    /// The dynamic offset of [BindGroup] selecting every agent.
+    pub(crate) const ALL_AGENTS: u32 = 0;
+}
 
 #[derive(Resource, Deref, DerefMut, Default)]
-struct QualitiesMap(HashMap<Entity, QualitiesBuffer>);
+/// The first agent, number of agents and first slot of each [Batch].
+struct BatchBuffer(DynamicUniformBuffer<UVec3>);
+
+#[derive(Resource, Default)]
+/// Assigns each species a slot in `0..MAX_SPECIES`, which selects its channel of the trail map and
+/// indexes per-species data on the GPU. Species without a slot are not simulated. Lives in the
+/// Render world.
+pub(crate) struct Slots(HashMap<Entity, usize>);
+
+impl Slots {
+    pub(crate) fn get(&self, id: Entity) -> Option<usize> {
+        self.0.get(&id).copied()
+    }
+
+    pub(crate) fn iter(&self) -> impl Iterator<Item = (Entity, usize)> + '_ {
+        self.0.iter().map(|(&id, &slot)| (id, slot))
+    }
+
+    /// Frees the slots of species that no longer exist and assigns free slots to new species.
+    /// Returns whether any assignment changed.
+    fn update(&mut self, species: &[Entity]) -> bool {
+        let before = self.0.len();
+        self.0.retain(|id, _| species.contains(id));
+        let mut changed = self.0.len() != before;
+        for &id in species {
+            if self.0.contains_key(&id) {
+                continue;
+            }
+            match (0..MAX_SPECIES).find(|slot| !self.0.values().any(|used| used == slot)) {
+                Some(slot) => {
+                    self.0.insert(id, slot);
+                    changed = true;
+                }
+                None => warn!(
+                    "no slot for species {:?}: at most {} species are supported",
+                    id, MAX_SPECIES
+                ),
+            }
+        }
+        changed
+    }
+}
+
+#[derive(Component, Debug, Deref)]
+/// Indicates the agents for a species need to be intitialized, and holds the number of agents of
+/// the species.
+pub struct Uninitialized(u32);
 
 #[derive(Component, Debug)]
-/// Marker component that indicates the agents for a species need to be intitialized.
-pub struct Uninitialized;
+/// Re-spawns all of a species' agents according to its [SpawnPattern]. Insert it on a species;
+/// it is removed once handled.
+pub struct Reinitialize;
 
-fn render_clear_deleted(
-    agents_map: Option<ResMut<AgentsMap>>,
-    mut removals: RemovedComponents<NumAgents>,
+fn clear_reinitialize(mut commands: Commands, query: Query<Entity, With<Reinitialize>>) {
+    for id in &query {
+        commands.entity(id).remove::<Reinitialize>();
+    }
+}
+
+pub(crate) fn render_extract_slots(
+    mut slots: ResMut<Slots>,
+    query: Extract<Query<Entity, With<NumAgents>>>,
 ) {
-    if let Some(mut agents_map) = agents_map {
-        for entity in removals.iter() {
-            println!("removing buffers for species: {:?}", entity);
-            agents_map.remove(&entity);
-        }
+    let species: Vec<_> = query.iter().collect();
+    // only flag the slots as changed when an assignment actually changed
+    if slots.bypass_change_detection().update(&species) {
+        slots.set_changed();
     }
 }
 
-fn render_extract_qualities_buffer(
-    mut commands: Commands,
-    device: Res<RenderDevice>,
+fn render_extract_qualities(
     queue: Res<RenderQueue>,
-    mut qualities_map: ResMut<QualitiesMap>,
+    slots: Res<Slots>,
+    buffer: Res<QualitiesBuffer>,
     query: Extract<Query<(Entity, Ref<Qualities>)>>,
 ) {
-    let mut qualities_components = vec![];
     for (id, qualities) in &query {
-        let qualities_buffer = qualities_map.entry(id).or_insert_with(|| {
-            println!("creating new qualities buffer: {:?}", id);
-            QualitiesBuffer(device.create_buffer(&BufferDescriptor {
-                label: Some(&format!("[species {:?}] qualities", id)),
-                usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST,
-                size: std::mem::size_of::<GpuQualities>() as u64,
-                mapped_at_creation: false,
-            }))
-        });
-        if qualities.is_changed() {
-            let gpu_qualities = GpuQualities::from(qualities.clone());
-            queue.write_buffer(qualities_buffer, 0, bytemuck::bytes_of(&gpu_qualities));
+        let Some(slot) = slots.get(id) else {
+            continue;
+        };
+        if qualities.is_changed() || slots.is_changed() {
+            let gpu_qualities = GpuQualities::new(qualities.clone(), slot);
+            queue.write_buffer(
+                &buffer,
+                (slot * std::mem::size_of::<GpuQualities>()) as u64,
+                bytemuck::bytes_of(&gpu_qualities),
+            );
+        }
+    }
+}
+
+fn render_extract_interactions(
+    queue: Res<RenderQueue>,
+    slots: Res<Slots>,
+    buffer: Res<InteractionsBuffer>,
+    query: Extract<Query<(Entity, Ref<Interactions>)>>,
+) {
+    // any change to the set of species or their weights invalidates every row
+    if !slots.is_changed()
+        && !query
+            .iter()
+            .any(|(_, interactions)| interactions.is_changed())
+    {
+        return;
+    }
+    let mut matrix = [InteractionsRow::default(); MAX_SPECIES];
+    for (id, interactions) in &query {
+        let Some(slot) = slots.get(id) else {
+            continue;
+        };
+        for (other, _) in &query {
+            if let Some(other_slot) = slots.get(other) {
+                matrix[slot][other_slot] = interactions.weight(id, other);
+            }
         }
-        qualities_components.push((id, qualities_buffer.clone()));
     }
-    commands.insert_or_spawn_batch(qualities_components);
+    queue.write_buffer(&buffer, 0, bytemuck::cast_slice(&matrix));
 }
 
-// extract [AgentsBuffer] for each species
-fn render_extract_agents_buffer(
+/// Rebuilds the shared agent buffer whenever a species is added, removed, resized or reinitialized,
+/// keeping the agents that survive.
+#[allow(clippy::type_complexity, clippy::too_many_arguments)]
+fn render_extract_agents(
     mut commands: Commands,
     device: Res<RenderDevice>,
-    agents_map: Option<ResMut<AgentsMap>>,
-    query: Extract<Query<(Entity, Ref<NumAgents>)>>,
+    queue: Res<RenderQueue>,
+    agents: Option<ResMut<Agents>>,
+    slots: Res<Slots>,
+    populations: Res<PopulationBuffer>,
+    limit: Res<AgentLimit>,
+    mut spawn_map: ResMut<SpawnMap>,
+    gpu_images: Res<RenderAssets<Image>>,
+    query: Extract<
+        Query<(
+            Entity,
+            &NumAgents,
+            &Qualities,
+            Ref<SpawnPattern>,
+            Option<&Reinitialize>,
+        )>,
+    >,
 ) {
-    if let Some(mut agents_map) = agents_map {
-        let mut agents_buffer_components = vec![];
-        let mut uninitialized = vec![];
-        for (id, num_agents) in &query {
-            if num_agents.is_changed() {
-                agents_map.remove(&id);
+    let Some(mut agents) = agents else {
+        return;
+    };
+
+    // lay the species out by slot
+    let mut species: [Option<(Entity, &SpawnPattern, bool, u32)>; MAX_SPECIES] = Default::default();
+    let mut ranges: [Option<(Entity, Range<u32>)>; MAX_SPECIES] = Default::default();
+    let mut lifecycles = [false; MAX_SPECIES];
+    for (id, num_agents, qualities, pattern, reinitialize) in &query {
+        let Some(slot) = slots.get(id) else {
+            continue;
+        };
+        if let SpawnPattern::Image(image) = &*pattern {
+            if !gpu_images.contains_key(image) {
+                // the species has no agents until the image loads
+                continue;
+            }
+        }
+        // species with a lifecycle spawn with their number of agents, but can grow to capacity
+        let (spawned, len) = match qualities.lifecycle {
+            Some(lifecycle) => ((**num_agents).min(lifecycle.capacity), lifecycle.capacity),
+            None => (**num_agents, **num_agents),
+        };
+        lifecycles[slot] = qualities.lifecycle.is_some();
+        // the agents of a species that gains or loses its lifecycle can't be kept
+        let toggled = agents.ranges[slot]
+            .as_ref()
+            .is_some_and(|(previous_id, _)| *previous_id == id)
+            && agents.lifecycles[slot] != lifecycles[slot];
+        let respawn = pattern.is_changed() || reinitialize.is_some() || toggled;
+        species[slot] = Some((id, pattern.into_inner(), respawn, spawned));
+        ranges[slot] = Some((id, 0..len));
+    }
+    // a single buffer (and binding) holds every agent
+    let device_limits = device.limits();
+    let max_agents = (device_limits.max_storage_buffer_binding_size as u64)
+        .min(device_limits.max_buffer_size)
+        / AGENT_SIZE;
+    let max_agents = max_agents.min(u32::MAX as u64) as u32;
+    let mut end = 0;
+    let mut clamped = false;
+    for (_, range) in ranges.iter_mut().flatten() {
+        let len = range.end.min(max_agents - end);
+        clamped |= len < range.end;
+        *range = end..end + len;
+        end = range.end;
+    }
+    if clamped && limit.get().is_none() {
+        warn!(
+            "the species ask for more agents than the GPU can hold, clamping to {} agents",
+            max_agents
+        );
+    }
+    limit.set(clamped.then_some(max_agents));
+    let respawn = species.iter().flatten().any(|(_, _, respawn, _)| *respawn);
+    if agents.buffer.is_some() && ranges == agents.ranges && !respawn {
+        return;
+    }
+
+    debug!("creating new agents buffer: {} agents", end);
+    let buffer = device.create_buffer(&BufferDescriptor {
+        label: "species::Agents".into(),
+        // storage buffers can't be empty
+        size: end.max(1) as u64 * AGENT_SIZE,
+        usage: BufferUsages::STORAGE | BufferUsages::COPY_SRC | BufferUsages::COPY_DST,
+        mapped_at_creation: false,
+    });
+    let next = device.create_buffer(&BufferDescriptor {
+        label: "species::NextAgents".into(),
+        size: end.max(1) as u64 * AGENT_SIZE,
+        usage: BufferUsages::STORAGE | BufferUsages::COPY_SRC,
+        mapped_at_creation: false,
+    });
+    // the shader offsets each species' tallies by its slot (see `first_tally`)
+    let tallies = device.create_buffer(&BufferDescriptor {
+        label: "species::Tallies".into(),
+        size: (end / super::WORKGROUP_SIZE + MAX_SPECIES as u32 + 1) as u64 * TALLY_SIZE,
+        usage: BufferUsages::STORAGE,
+        mapped_at_creation: false,
+    });
+    // species that lost their lifecycle no longer have a population
+    for slot in (0..MAX_SPECIES).filter(|&slot| agents.lifecycles[slot] && !lifecycles[slot]) {
+        queue.write_buffer(
+            &populations,
+            GpuPopulation::offset(slot),
+            bytemuck::bytes_of(&GpuPopulation::default()),
+        );
+    }
+    // copies the agents that survive
+    let mut encoder = None;
+    let mut uninitialized = vec![];
+    let mut spawn_images = vec![];
+    for (slot, (species, range)) in species.iter().zip(&ranges).enumerate() {
+        let (Some((id, pattern, respawn, spawned)), Some((_, range))) = (species, range) else {
+            continue;
+        };
+        let count = range.len() as u32;
+        let spawned = (*spawned).min(count);
+        // keep as many of the existing agents as fit, and spawn the rest
+        let kept = match (&agents.buffer, &agents.ranges[slot]) {
+            (Some(previous), Some((previous_id, previous_range)))
+                if previous_id == id && !respawn =>
+            {
+                let kept = count.min(previous_range.len() as u32);
+                if kept > 0 {
+                    encoder
+                        .get_or_insert_with(|| {
+                            device.create_command_encoder(&CommandEncoderDescriptor {
+                                label: "species::resize_agents".into(),
+                            })
+                        })
+                        .copy_buffer_to_buffer(
+                            previous,
+                            previous_range.start as u64 * AGENT_SIZE,
+                            &buffer,
+                            range.start as u64 * AGENT_SIZE,
+                            kept as u64 * AGENT_SIZE,
+                        );
+                }
+                kept
+            }
+            _ => 0,
+        };
+        let spawned = if lifecycles[slot] {
+            if kept > 0 {
+                // only the GPU knows how many of the kept agents are alive, so leave the population
+                // as it is; the shader clamps it to the new capacity
+                queue.write_buffer(
+                    &populations,
+                    GpuPopulation::capacity_offset(slot),
+                    bytemuck::bytes_of(&count),
+                );
+                continue;
             }
-            let entry = agents_map.entry(id);
-            let agents_buffer = entry.or_insert_with(|| {
-                uninitialized.push(id);
-                println!("creating new agents buffer: {:?}", id);
-                AgentsBuffer(device.create_buffer(&BufferDescriptor {
-                    label: Some(&format!("[species {:?}] agents", id)),
-                    size: *num_agents.clone() as u64 * (std::mem::size_of::<GpuAgent>() as u64),
-                    usage: BufferUsages::STORAGE,
+            queue.write_buffer(
+                &populations,
+                GpuPopulation::offset(slot),
+                bytemuck::bytes_of(&GpuPopulation::new(spawned, count)),
+            );
+            spawned
+        } else {
+            count
+        };
+        if kept < spawned {
+            let spawn_buffer = spawn_map.entry(*id).or_insert_with(|| {
+                debug!("creating new spawn buffer: {:?}", id);
+                SpawnBuffer(device.create_buffer(&BufferDescriptor {
+                    label: Some(&format!("[species {:?}] spawn pattern", id)),
+                    usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST,
+                    size: std::mem::size_of::<GpuSpawnPattern>() as u64,
                     mapped_at_creation: false,
                 }))
             });
-            agents_buffer_components.push((id, agents_buffer.clone()));
+            let spawn_range = range.start..range.start + spawned;
+            let gpu_pattern = GpuSpawnPattern::new(pattern, slot, spawn_range, kept);
+            queue.write_buffer(spawn_buffer, 0, bytemuck::bytes_of(&gpu_pattern));
+            uninitialized.push((*id, (Uninitialized(spawned), spawn_buffer.clone())));
+            if let SpawnPattern::Image(image) = pattern {
+                spawn_images.push((*id, SpawnImage(image.clone_weak())));
+            }
         }
-        commands.insert_or_spawn_batch(agents_buffer_components);
-        commands.insert_or_spawn_batch(uninitialized.into_iter().map(|id| (id, Uninitialized)));
     }
+    if let Some(encoder) = encoder {
+        queue.submit([encoder.finish()]);
+    }
+    spawn_map.retain(|id, _| slots.get(*id).is_some());
+    agents.buffer = Some(buffer);
+    agents.next = Some(next);
+    agents.tallies = Some(tallies);
+    agents.ranges = ranges;
+    agents.lifecycles = lifecycles;
+    commands.insert_or_spawn_batch(uninitialized);
+    commands.insert_or_spawn_batch(spawn_images);
 }
 
 #[derive(Resource, Deref, DerefMut)]
@@ -187,49 +939,273 @@ impl FromWorld for BindGroupLayout {
                 BindGroupLayoutEntry {
                     binding: 1,
                     visibility: ShaderStages::COMPUTE,
+                    ty: BindingType::Buffer {
+                        ty: BufferBindingType::Storage { read_only: true },
+                        has_dynamic_offset: false,
+                        min_binding_size: Some(
+                            ((MAX_SPECIES * std::mem::size_of::<GpuQualities>()) as u64)
+                                .try_into()
+                                .unwrap(),
+                        ),
+                    },
+                    count: None,
+                },
+                BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: ShaderStages::COMPUTE,
+                    ty: BindingType::Buffer {
+                        ty: BufferBindingType::Storage { read_only: true },
+                        has_dynamic_offset: false,
+                        min_binding_size: Some(
+                            ((MAX_SPECIES * std::mem::size_of::<InteractionsRow>()) as u64)
+                                .try_into()
+                                .unwrap(),
+                        ),
+                    },
+                    count: None,
+                },
+                BindGroupLayoutEntry {
+                    binding: 3,
+                    visibility: ShaderStages::COMPUTE,
                     ty: BindingType::Buffer {
                         ty: BufferBindingType::Uniform,
+                        has_dynamic_offset: true,
+                        min_binding_size: Some(UVec3::min_size()),
+                    },
+                    count: None,
+                },
+                BindGroupLayoutEntry {
+                    binding: 4,
+                    visibility: ShaderStages::COMPUTE,
+                    ty: BindingType::Buffer {
+                        ty: BufferBindingType::Storage { read_only: false },
                         has_dynamic_offset: false,
                         min_binding_size: Some(
-                            (std::mem::size_of::<GpuQualities>() as u64)
+                            ((MAX_SPECIES * std::mem::size_of::<GpuPopulation>()) as u64)
                                 .try_into()
                                 .unwrap(),
                         ),
                     },
                     count: None,
                 },
+                BindGroupLayoutEntry {
+                    binding: 5,
+                    visibility: ShaderStages::COMPUTE,
+                    ty: BindingType::Buffer {
+                        ty: BufferBindingType::Storage { read_only: false },
+                        has_dynamic_offset: false,
+                        min_binding_size: None, // laid out like the agents
+                    },
+                    count: None,
+                },
+                BindGroupLayoutEntry {
+                    binding: 6,
+                    visibility: ShaderStages::COMPUTE,
+                    ty: BindingType::Buffer {
+                        ty: BufferBindingType::Storage { read_only: false },
+                        has_dynamic_offset: false,
+                        min_binding_size: BufferSize::new(TALLY_SIZE),
+                    },
+                    count: None,
+                },
             ],
         });
         Self(layout)
     }
 }
 
-#[derive(Component, Deref, DerefMut)]
+#[derive(Resource, Deref, DerefMut)]
+/// Binds the agents, qualities, interactions and populations of every species, the agents of a
+/// [Batch], and the buffers the lifecycle passes pack agents with.
 pub struct BindGroup(bevy::render::render_resource::BindGroup);
 
+#[derive(Resource, Deref, DerefMut)]
+pub(crate) struct SpawnBindGroupLayout(bevy::render::render_resource::BindGroupLayout);
+
+impl FromWorld for SpawnBindGroupLayout {
+    fn from_world(world: &mut World) -> Self {
+        let device: &RenderDevice = world.resource();
+        let layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+            label: "species::SpawnBindGroupLayout".into(),
+            entries: &[
+                BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: ShaderStages::COMPUTE,
+                    ty: BindingType::Texture {
+                        // only loaded, so any float image will do
+                        sample_type: TextureSampleType::Float { filterable: false },
+                        view_dimension: TextureViewDimension::D2,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+                BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: ShaderStages::COMPUTE,
+                    ty: BindingType::Buffer {
+                        ty: BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: Some(
+                            (std::mem::size_of::<GpuSpawnPattern>() as u64)
+                                .try_into()
+                                .unwrap(),
+                        ),
+                    },
+                    count: None,
+                },
+            ],
+        });
+        Self(layout)
+    }
+}
+
+#[derive(Component, Deref, DerefMut, Clone)]
+/// Binds the species' [SpawnPattern] and [SpawnImage] (or a fallback) during initialization.
+pub struct SpawnBindGroup(bevy::render::render_resource::BindGroup);
+
+#[derive(Resource, Deref, DerefMut, Default)]
+/// The [SpawnBindGroup] of each species, and the texture it was created for.
+struct SpawnBindGroups(HashMap<Entity, (TextureId, SpawnBindGroup)>);
+
+/// The per-species buffers bound next to the agents.
+#[derive(SystemParam)]
+struct SpeciesBuffers<'w> {
+    qualities: Res<'w, QualitiesBuffer>,
+    interactions: Res<'w, InteractionsBuffer>,
+    populations: Res<'w, PopulationBuffer>,
+}
+
+/// Recreates the bind group over the agents whenever the agent buffer is rebuilt or the batches
+/// change, and the spawn bind group of a species whenever its spawn image changes.
+#[allow(clippy::too_many_arguments, clippy::type_complexity)]
 fn render_queue_bind_groups(
     mut commands: Commands,
-    query: Query<(Entity, &QualitiesBuffer, &AgentsBuffer)>,
+    query: Query<(Entity, &SpawnBuffer, Option<&SpawnImage>)>,
     device: Res<RenderDevice>,
+    queue: Res<RenderQueue>,
+    agents: Option<Res<Agents>>,
+    slot_pipelines: Res<SlotPipelines>,
+    mut batches: ResMut<Batches>,
+    buffers: SpeciesBuffers,
+    spawn_map: Res<SpawnMap>,
+    mut spawn_bind_groups: ResMut<SpawnBindGroups>,
+    mut batch_buffer: ResMut<BatchBuffer>,
     layout: Res<BindGroupLayout>,
+    spawn_layout: Res<SpawnBindGroupLayout>,
+    gpu_images: Res<RenderAssets<Image>>,
+    fallback_image: Res<FallbackImage>,
 ) {
-    let mut components = vec![];
-    for (id, qualities, agents) in &query {
+    let Some(agents) = agents else {
+        return;
+    };
+    batches.vacated = Default::default();
+    let changed = agents.is_changed() || slot_pipelines.is_changed();
+    if let (true, Some(agents_buffer), Some(next_buffer), Some(tallies_buffer)) =
+        (changed, &agents.buffer, &agents.next, &agents.tallies)
+    {
+        batch_buffer.clear();
+        let len = agents
+            .ranges
+            .iter()
+            .flatten()
+            .map(|(_, agents)| agents.end)
+            .max();
+        batch_buffer.push(UVec3::new(0, len.unwrap_or(0), 0));
+        let mut batch = |(key, slot, range): (PipelineKey, usize, Range<u32>)| Batch {
+            key,
+            population: agents.lifecycles[slot].then_some(slot),
+            offset: batch_buffer.push(UVec3::new(range.start, range.len() as u32, slot as u32)),
+            agents: range,
+        };
+        let project: [Vec<Batch>; trail::LAYERS] = std::array::from_fn(|layer| {
+            let slots = layer * trail::SPECIES_PER_LAYER..(layer + 1) * trail::SPECIES_PER_LAYER;
+            agents
+                .runs(&slot_pipelines, slots)
+                .into_iter()
+                .map(&mut batch)
+                .collect()
+        });
+        let occupied = std::array::from_fn(|layer| !project[layer].is_empty());
+        *batches = Batches {
+            update: agents
+                .runs(&slot_pipelines, 0..MAX_SPECIES)
+                .into_iter()
+                .map(&mut batch)
+                .collect(),
+            project,
+            occupied,
+            vacated: std::array::from_fn(|layer| batches.occupied[layer] && !occupied[layer]),
+        };
+        batch_buffer.write_buffer(&device, &queue);
+
         let bind_group = device.create_bind_group(&BindGroupDescriptor {
-            label: Some(&format!("species::BindGroup({:?})", id)),
+            label: "species::BindGroup".into(),
             layout: &layout,
             entries: &[
                 BindGroupEntry {
                     binding: 0,
-                    resource: agents.as_entire_binding(),
+                    resource: agents_buffer.as_entire_binding(),
                 },
                 BindGroupEntry {
                     binding: 1,
-                    resource: qualities.as_entire_binding(),
+                    resource: buffers.qualities.as_entire_binding(),
+                },
+                BindGroupEntry {
+                    binding: 2,
+                    resource: buffers.interactions.as_entire_binding(),
+                },
+                BindGroupEntry {
+                    binding: 3,
+                    resource: batch_buffer.binding().unwrap(),
+                },
+                BindGroupEntry {
+                    binding: 4,
+                    resource: buffers.populations.as_entire_binding(),
+                },
+                BindGroupEntry {
+                    binding: 5,
+                    resource: next_buffer.as_entire_binding(),
+                },
+                BindGroupEntry {
+                    binding: 6,
+                    resource: tallies_buffer.as_entire_binding(),
                 },
             ],
         });
-        components.push((id, BindGroup(bind_group)));
+        commands.insert_resource(BindGroup(bind_group));
+    }
+
+    // only species being initialized this frame have a spawn buffer
+    spawn_bind_groups.retain(|id, _| spawn_map.contains_key(id));
+    let mut components = vec![];
+    for (id, spawn, spawn_image) in &query {
+        let spawn_image = spawn_image
+            .and_then(|image| gpu_images.get(image))
+            .unwrap_or(&fallback_image);
+        let texture = spawn_image.texture.id();
+        let cached = spawn_bind_groups
+            .get(&id)
+            .filter(|(bound, _)| *bound == texture)
+            .map(|(_, bind_group)| bind_group.clone());
+        let spawn_bind_group = cached.unwrap_or_else(|| {
+            let bind_group = SpawnBindGroup(device.create_bind_group(&BindGroupDescriptor {
+                label: Some(&format!("species::SpawnBindGroup({:?})", id)),
+                layout: &spawn_layout,
+                entries: &[
+                    BindGroupEntry {
+                        binding: 0,
+                        resource: BindingResource::TextureView(&spawn_image.texture_view),
+                    },
+                    BindGroupEntry {
+                        binding: 1,
+                        resource: spawn.as_entire_binding(),
+                    },
+                ],
+            }));
+            spawn_bind_groups.insert(id, (texture, bind_group.clone()));
+            bind_group
+        });
+        components.push((id, spawn_bind_group));
     }
     commands.insert_or_spawn_batch(components);
 }
@@ -239,12 +1215,41 @@ pub struct Plugin;
 impl bevy::app::Plugin for Plugin {
     fn build(&self, app: &mut App) {
         // app.add_plugin(ExtractComponentPlugin::<Species>::default());
+        let limit = AgentLimit::default();
+        app.add_system(clear_reinitialize.in_base_set(CoreSet::First))
+            .insert_resource(limit.clone());
         app.sub_app_mut(RenderApp)
-            .init_resource::<QualitiesMap>()
+            // shares the limit with the main world
+            .insert_resource(limit)
+            .init_resource::<QualitiesBuffer>()
+            .init_resource::<InteractionsBuffer>()
+            .init_resource::<SpawnMap>()
+            .init_resource::<SpawnBindGroups>()
+            .init_resource::<BatchBuffer>()
+            .init_resource::<Batches>()
+            .init_resource::<Slots>()
             .init_resource::<BindGroupLayout>()
-            .add_system(render_queue_bind_groups.in_set(RenderSet::Queue))
-            .add_system(render_extract_agents_buffer.in_schedule(ExtractSchedule))
-            .add_system(render_extract_qualities_buffer.in_schedule(ExtractSchedule))
-            .add_system(render_clear_deleted.in_schedule(ExtractSchedule));
+            .init_resource::<SpawnBindGroupLayout>()
+            .add_system(
+                render_queue_bind_groups
+                    .in_set(RenderSet::Queue)
+                    .after(behavior::render_queue_specialized_pipelines),
+            )
+            .add_system(render_extract_slots.in_schedule(ExtractSchedule))
+            .add_system(
+                render_extract_agents
+                    .in_schedule(ExtractSchedule)
+                    .after(render_extract_slots),
+            )
+            .add_system(
+                render_extract_qualities
+                    .in_schedule(ExtractSchedule)
+                    .after(render_extract_slots),
+            )
+            .add_system(
+                render_extract_interactions
+                    .in_schedule(ExtractSchedule)
+                    .after(render_extract_slots),
+            );
     }
 }
@@ -0,0 +1,57 @@
+use bevy::{
+    prelude::*,
+    render::extract_resource::{ExtractResource, ExtractResourcePlugin},
+};
+
+/// Controls how simulated time advances. The simulation advances in fixed steps, so it evolves the
+/// same way regardless of the frame rate.
+#[derive(Resource, Clone, Debug, ExtractResource)]
+pub struct Timestep {
+    /// Simulated seconds per step.
+    pub step: f32,
+    /// The most steps simulated per rendered frame. When rendering can't keep up, the simulation
+    /// slows down instead of falling further and further behind.
+    pub max_substeps: u32,
+    /// Simulated seconds per real second. Zero pauses the simulation.
+    pub speed: f32,
+}
+
+impl Default for Timestep {
+    fn default() -> Self {
+        Self {
+            step: 1.0 / 60.0,
+            max_substeps: 8,
+            speed: 1.0,
+        }
+    }
+}
+
+/// The number of steps to simulate during this frame.
+#[derive(Resource, Clone, Copy, Default, Deref, ExtractResource)]
+pub(crate) struct Steps(u32);
+
+fn accumulate_steps(
+    time: Res<Time>,
+    timestep: Res<Timestep>,
+    mut steps: ResMut<Steps>,
+    // simulated seconds not yet stepped
+    mut accumulator: Local<f32>,
+) {
+    *accumulator += time.delta_seconds() * timestep.speed.max(0.0);
+    let due = (*accumulator / timestep.step.max(f32::EPSILON)).floor();
+    // drop the steps we can't keep up with rather than carrying them over
+    *accumulator -= due * timestep.step;
+    *steps = Steps((due as u32).min(timestep.max_substeps));
+}
+
+pub(crate) struct Plugin;
+
+impl bevy::app::Plugin for Plugin {
+    fn build(&self, app: &mut App) {
+        app.add_plugin(ExtractResourcePlugin::<Timestep>::default())
+            .add_plugin(ExtractResourcePlugin::<Steps>::default())
+            .init_resource::<Timestep>()
+            .init_resource::<Steps>()
+            .add_system(accumulate_steps.in_base_set(CoreSet::PreUpdate));
+    }
+}
@@ -1,25 +1,100 @@
-use crate::Framebuffers;
+use std::num::NonZeroU32;
+
+use super::species::MAX_SPECIES;
+use crate::{Framebuffers, Resolution};
 use bevy::{
     prelude::*,
     render::{
+        extract_resource::{ExtractResource, ExtractResourcePlugin},
         render_asset::RenderAssets,
         render_resource::{
             AddressMode, BindGroup, BindGroupDescriptor, BindGroupEntry, BindGroupLayout,
-            BindGroupLayoutDescriptor, BindGroupLayoutEntry, BindingResource, BindingType,
-            FilterMode, SamplerBindingType, SamplerDescriptor, ShaderStages, StorageTextureAccess,
-            TextureFormat, TextureSampleType, TextureViewDimension,
+            BindGroupLayoutDescriptor, BindGroupLayoutEntry, BindingResource, BindingType, Buffer,
+            BufferBindingType, BufferDescriptor, BufferSize, BufferUsages, CommandEncoder,
+            FilterMode, SamplerBindingType, SamplerDescriptor, ShaderStages, TextureFormat,
+            TextureId, TextureSampleType, TextureView, TextureViewDescriptor, TextureViewDimension,
         },
         renderer::RenderDevice,
+        texture::FallbackImage,
         RenderApp, RenderSet,
     },
 };
 
+/// Each layer of the trail map holds the chemical trails of four species, one per channel.
+pub const SPECIES_PER_LAYER: usize = 4;
+
+/// The number of layers in the trail map. A species' slot determines its layer and channel.
+pub const LAYERS: usize = MAX_SPECIES / SPECIES_PER_LAYER;
+
+/// Storage format of the trail map. Changes to a trail smaller than the precision of the format
+/// are lost, so slow evaporation needs a floating point format.
+#[derive(Resource, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum TrailFormat {
+    /// 8 bits per channel. Evaporation rates below 1/255 have no effect.
+    Rgba8Unorm,
+    /// Half precision floats.
+    #[default]
+    Rgba16Float,
+    /// Single precision floats. These cannot be filtered, so the trail map is sampled without
+    /// interpolation.
+    Rgba32Float,
+}
+
+impl TrailFormat {
+    pub fn texture_format(self) -> TextureFormat {
+        match self {
+            Self::Rgba8Unorm => TextureFormat::Rgba8Unorm,
+            Self::Rgba16Float => TextureFormat::Rgba16Float,
+            Self::Rgba32Float => TextureFormat::Rgba32Float,
+        }
+    }
+
+    /// Whether the trail map can be sampled with linear filtering.
+    pub(crate) fn filterable(self) -> bool {
+        !matches!(self, Self::Rgba32Float)
+    }
+
+    fn sampler_binding_type(self) -> SamplerBindingType {
+        if self.filterable() {
+            SamplerBindingType::Filtering
+        } else {
+            SamplerBindingType::NonFiltering
+        }
+    }
+}
+
+/// An image marking the impassable regions of the world: agents cannot enter texels darker than
+/// half brightness, don't sense or deposit trails in them, and trails never diffuse into them. The
+/// image is stretched over the world, whatever its size.
+///
+/// Half brightness is mid-grey in sRGB (128 of 255), which is how PNGs and painted masks are
+/// stored. Masks in linear formats are compared in linear space, where mid-grey is about 0.214.
+///
+/// Load it from a PNG asset, or paint it at runtime by modifying the image in `Assets<Image>`.
+/// `None` leaves the whole world open.
+#[derive(Resource, Clone, Debug, Default, PartialEq, Eq, Deref, DerefMut, ExtractResource)]
+pub struct Obstacles(pub Option<Handle<Image>>);
+
+/// The binding of the [Obstacles] mask, next to the trail map. The mask is loaded rather than
+/// sampled, so any image format works.
+const OBSTACLES_ENTRY: BindGroupLayoutEntry = BindGroupLayoutEntry {
+    binding: 2,
+    visibility: ShaderStages::COMPUTE.union(ShaderStages::FRAGMENT),
+    ty: BindingType::Texture {
+        sample_type: TextureSampleType::Float { filterable: false },
+        view_dimension: TextureViewDimension::D2,
+        multisampled: false,
+    },
+    count: None,
+};
+
 #[derive(Resource, Deref, DerefMut)]
 pub(crate) struct TextureBindGroupLayout(BindGroupLayout);
 
 impl FromWorld for TextureBindGroupLayout {
     fn from_world(world: &mut World) -> Self {
         let device: &RenderDevice = world.resource::<RenderDevice>();
+        let format: TrailFormat = *world.resource();
         let layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
             label: "TextureBindGroupLayout".into(),
             entries: &[
@@ -27,7 +102,9 @@ impl FromWorld for TextureBindGroupLayout {
                     binding: 0,
                     visibility: ShaderStages::COMPUTE | ShaderStages::FRAGMENT,
                     ty: BindingType::Texture {
-                        sample_type: TextureSampleType::Float { filterable: true },
+                        sample_type: TextureSampleType::Float {
+                            filterable: format.filterable(),
+                        },
                         view_dimension: TextureViewDimension::D2,
                         multisampled: false,
                     },
@@ -36,9 +113,46 @@ impl FromWorld for TextureBindGroupLayout {
                 BindGroupLayoutEntry {
                     binding: 1,
                     visibility: ShaderStages::COMPUTE | ShaderStages::FRAGMENT,
-                    ty: BindingType::Sampler(SamplerBindingType::Filtering),
+                    ty: BindingType::Sampler(format.sampler_binding_type()),
+                    count: None,
+                },
+                OBSTACLES_ENTRY,
+            ],
+        });
+        Self(layout)
+    }
+}
+
+#[derive(Resource, Deref, DerefMut)]
+/// Like [TextureBindGroupLayout], but binds every layer of the trail map at once.
+pub(crate) struct ArrayTextureBindGroupLayout(BindGroupLayout);
+
+impl FromWorld for ArrayTextureBindGroupLayout {
+    fn from_world(world: &mut World) -> Self {
+        let device: &RenderDevice = world.resource::<RenderDevice>();
+        let format: TrailFormat = *world.resource();
+        let layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+            label: "ArrayTextureBindGroupLayout".into(),
+            entries: &[
+                BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: ShaderStages::COMPUTE | ShaderStages::FRAGMENT,
+                    ty: BindingType::Texture {
+                        sample_type: TextureSampleType::Float {
+                            filterable: format.filterable(),
+                        },
+                        view_dimension: TextureViewDimension::D2Array,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+                BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: ShaderStages::COMPUTE | ShaderStages::FRAGMENT,
+                    ty: BindingType::Sampler(format.sampler_binding_type()),
                     count: None,
                 },
+                OBSTACLES_ENTRY,
             ],
         });
         Self(layout)
@@ -46,97 +160,252 @@ impl FromWorld for TextureBindGroupLayout {
 }
 
 #[derive(Resource)]
+/// Single-layer views of each framebuffer, which the blur renders into one layer at a time.
+pub(crate) struct LayerViews {
+    pub(crate) primary: Vec<TextureView>,
+    pub(crate) secondary: Vec<TextureView>,
+}
+
+#[derive(Resource)]
+/// Binds a single layer of each framebuffer, indexed by layer.
 pub(crate) struct TextureBindGroups {
-    pub(crate) primary: BindGroup,
-    pub(crate) secondary: BindGroup,
+    pub(crate) primary: Vec<BindGroup>,
+    pub(crate) secondary: Vec<BindGroup>,
 }
 
+#[derive(Resource, Deref)]
+/// Binds every layer of the primary framebuffer.
+pub(crate) struct ArrayTextureBindGroup(BindGroup);
+
+/// Recreates the views and bind groups of the framebuffers whenever their textures change, e.g.
+/// when the [Resolution] changes, and whenever the [Obstacles] mask changes.
+#[allow(clippy::too_many_arguments)]
 fn queue_texture_bind_groups(
     mut commands: Commands,
     framebuffers: Res<Framebuffers>,
+    obstacles: Res<Obstacles>,
     sampler: Res<Sampler>,
     gpu_images: Res<RenderAssets<Image>>,
+    fallback_image: Res<FallbackImage>,
     layout: Res<TextureBindGroupLayout>,
+    array_layout: Res<ArrayTextureBindGroupLayout>,
     device: Res<RenderDevice>,
+    // the framebuffer and obstacle textures the bind groups were created for
+    mut bound: Local<Option<[TextureId; 3]>>,
 ) {
-    let [primary, secondary] = [0, 1].map(|i| {
-        device.create_bind_group(&BindGroupDescriptor {
-            label: Some(&format!("simulate::TextureBindGroup_{}", i)),
-            layout: &layout,
-            entries: &[
-                BindGroupEntry {
-                    binding: 0,
-                    resource: BindingResource::TextureView(
-                        &gpu_images[&framebuffers[i]].texture_view,
-                    ),
-                },
-                BindGroupEntry {
-                    binding: 1,
-                    resource: BindingResource::Sampler(&sampler),
-                },
-            ],
-        })
+    // the fallback image is white, so the world is open until the mask is loaded
+    let obstacles = obstacles
+        .0
+        .as_ref()
+        .and_then(|image| gpu_images.get(image))
+        .unwrap_or(&fallback_image);
+    let textures = [
+        gpu_images[&framebuffers[0]].texture.id(),
+        gpu_images[&framebuffers[1]].texture.id(),
+        obstacles.texture.id(),
+    ];
+    if *bound == Some(textures) {
+        return;
+    }
+    *bound = Some(textures);
+
+    let [primary_views, secondary_views] = [0, 1].map(|i| {
+        (0..LAYERS as u32)
+            .map(|layer| {
+                gpu_images[&framebuffers[i]]
+                    .texture
+                    .create_view(&TextureViewDescriptor {
+                        label: Some(&format!("trail::LayerView_{}[{}]", i, layer)),
+                        dimension: Some(TextureViewDimension::D2),
+                        base_array_layer: layer,
+                        array_layer_count: NonZeroU32::new(1),
+                        ..default()
+                    })
+            })
+            .collect::<Vec<_>>()
+    });
+    let [primary, secondary] = [&primary_views, &secondary_views].map(|views| {
+        views
+            .iter()
+            .enumerate()
+            .map(|(layer, view)| {
+                device.create_bind_group(&BindGroupDescriptor {
+                    label: Some(&format!("simulate::TextureBindGroup[{}]", layer)),
+                    layout: &layout,
+                    entries: &[
+                        BindGroupEntry {
+                            binding: 0,
+                            resource: BindingResource::TextureView(view),
+                        },
+                        BindGroupEntry {
+                            binding: 1,
+                            resource: BindingResource::Sampler(&sampler),
+                        },
+                        BindGroupEntry {
+                            binding: 2,
+                            resource: BindingResource::TextureView(&obstacles.texture_view),
+                        },
+                    ],
+                })
+            })
+            .collect()
+    });
+    let array = device.create_bind_group(&BindGroupDescriptor {
+        label: "simulate::ArrayTextureBindGroup".into(),
+        layout: &array_layout,
+        entries: &[
+            BindGroupEntry {
+                binding: 0,
+                resource: BindingResource::TextureView(&gpu_images[&framebuffers[0]].texture_view),
+            },
+            BindGroupEntry {
+                binding: 1,
+                resource: BindingResource::Sampler(&sampler),
+            },
+            BindGroupEntry {
+                binding: 2,
+                resource: BindingResource::TextureView(&obstacles.texture_view),
+            },
+        ],
     });
     commands.insert_resource(TextureBindGroups { primary, secondary });
+    commands.insert_resource(ArrayTextureBindGroup(array));
+    commands.insert_resource(LayerViews {
+        primary: primary_views,
+        secondary: secondary_views,
+    });
 }
 
+/// Deposits are accumulated as fixed point integers so that they can be added atomically.
+/// The shader scales them back down when resolving them into the trail map.
+const DEPOSIT_SIZE: u64 = std::mem::size_of::<u32>() as u64;
+
+/// The horizontal blur pass keeps each texel of the layer it blurs, so that the vertical pass can
+/// diffuse between the unblurred and fully blurred layer (it can't read the layer it writes).
+const UNBLURRED_SIZE: u64 = std::mem::size_of::<[f32; SPECIES_PER_LAYER]>() as u64;
+
 #[derive(Resource, Deref, DerefMut)]
-pub(crate) struct StorageTextureBindGroupLayout(BindGroupLayout);
+pub(crate) struct DepositBindGroupLayout(BindGroupLayout);
 
-impl FromWorld for StorageTextureBindGroupLayout {
+impl FromWorld for DepositBindGroupLayout {
     fn from_world(world: &mut World) -> Self {
         let device: &RenderDevice = world.resource::<RenderDevice>();
         let layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
-            label: "StorageTextureBindGroupLayout".into(),
-            entries: &[BindGroupLayoutEntry {
-                binding: 0,
-                visibility: ShaderStages::COMPUTE,
-                ty: BindingType::StorageTexture {
-                    access: StorageTextureAccess::WriteOnly,
-                    format: TextureFormat::Rgba8Unorm,
-                    view_dimension: TextureViewDimension::D2,
+            label: "DepositBindGroupLayout".into(),
+            entries: &[
+                BindGroupLayoutEntry {
+                    binding: 0,
+                    // agents deposit in the compute pass, and the blur resolves the deposits
+                    visibility: ShaderStages::COMPUTE | ShaderStages::FRAGMENT,
+                    ty: BindingType::Buffer {
+                        ty: BufferBindingType::Storage { read_only: false },
+                        has_dynamic_offset: false,
+                        min_binding_size: BufferSize::new(DEPOSIT_SIZE),
+                    },
+                    count: None,
                 },
-                count: None,
-            }],
+                BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: ShaderStages::FRAGMENT,
+                    ty: BindingType::Buffer {
+                        ty: BufferBindingType::Storage { read_only: false },
+                        has_dynamic_offset: false,
+                        min_binding_size: BufferSize::new(UNBLURRED_SIZE),
+                    },
+                    count: None,
+                },
+            ],
         });
         Self(layout)
     }
 }
 
-#[derive(Resource, Deref)]
-/// Stores into the primary texture.
-pub(crate) struct StorageTextureBindGroup(BindGroup);
+#[derive(Resource)]
+/// Binds the buffer accumulating the deposits of the species in a single layer, one value per
+/// texel and channel, and the unblurred texels of the layer being blurred. The layers are
+/// projected and resolved one after another, so the buffers are shared by all of them.
+pub(crate) struct DepositBindGroup {
+    bind_group: BindGroup,
+    deposits: Buffer,
+}
+
+impl std::ops::Deref for DepositBindGroup {
+    type Target = BindGroup;
+
+    fn deref(&self) -> &BindGroup {
+        &self.bind_group
+    }
+}
 
-fn queue_storage_texture_bind_groups(
+impl DepositBindGroup {
+    /// Discards the deposits that haven't been resolved yet.
+    pub(crate) fn clear(&self, encoder: &mut CommandEncoder) {
+        encoder.clear_buffer(&self.deposits, 0, None);
+    }
+}
+
+/// Recreates the deposit and unblurred buffers whenever the [Resolution] changes.
+fn prepare_deposit_bind_group(
     mut commands: Commands,
-    framebuffers: Res<Framebuffers>,
-    gpu_images: Res<RenderAssets<Image>>,
-    layout: Res<StorageTextureBindGroupLayout>,
+    resolution: Res<Resolution>,
+    layout: Res<DepositBindGroupLayout>,
     device: Res<RenderDevice>,
 ) {
+    if !resolution.is_changed() {
+        return;
+    }
+    let texels = resolution.x as u64 * resolution.y as u64;
+    let buffer = device.create_buffer(&BufferDescriptor {
+        label: "trail::DepositBuffer".into(),
+        size: texels * SPECIES_PER_LAYER as u64 * DEPOSIT_SIZE,
+        usage: BufferUsages::STORAGE | BufferUsages::COPY_DST,
+        mapped_at_creation: false,
+    });
+    let unblurred = device.create_buffer(&BufferDescriptor {
+        label: "trail::UnblurredBuffer".into(),
+        size: texels * UNBLURRED_SIZE,
+        usage: BufferUsages::STORAGE,
+        mapped_at_creation: false,
+    });
     let bind_group = device.create_bind_group(&BindGroupDescriptor {
-        label: "trail::StorageTextureBindGroup".into(),
+        label: "trail::DepositBindGroup".into(),
         layout: &layout,
-        entries: &[BindGroupEntry {
-            binding: 0,
-            resource: BindingResource::TextureView(&gpu_images[&framebuffers[0]].texture_view),
-        }],
+        entries: &[
+            BindGroupEntry {
+                binding: 0,
+                resource: buffer.as_entire_binding(),
+            },
+            BindGroupEntry {
+                binding: 1,
+                resource: unblurred.as_entire_binding(),
+            },
+        ],
+    });
+    commands.insert_resource(DepositBindGroup {
+        bind_group,
+        deposits: buffer,
     });
-    commands.insert_resource(StorageTextureBindGroup(bind_group));
 }
 
 #[derive(Resource, Deref)]
-struct Sampler(bevy::render::render_resource::Sampler);
+pub(crate) struct Sampler(bevy::render::render_resource::Sampler);
 
 impl FromWorld for Sampler {
     fn from_world(world: &mut World) -> Self {
         let device: &RenderDevice = world.resource();
+        let format: TrailFormat = *world.resource();
+        let filter = if format.filterable() {
+            FilterMode::Linear
+        } else {
+            FilterMode::Nearest
+        };
         let sampler = device.create_sampler(&SamplerDescriptor {
             label: Some("TextureSampler"),
-            address_mode_u: AddressMode::ClampToEdge,
-            address_mode_v: AddressMode::ClampToEdge,
-            mag_filter: FilterMode::Linear,
-            min_filter: FilterMode::Linear,
+            // repeat so that wrapping worlds blur across the seam; the shader clamps otherwise
+            address_mode_u: AddressMode::Repeat,
+            address_mode_v: AddressMode::Repeat,
+            mag_filter: filter,
+            min_filter: filter,
             ..default()
         });
         Self(sampler)
@@ -147,11 +416,15 @@ pub(crate) struct Plugin;
 
 impl bevy::app::Plugin for Plugin {
     fn build(&self, app: &mut App) {
+        app.add_plugin(ExtractResourcePlugin::<Obstacles>::default())
+            .init_resource::<Obstacles>();
+
         app.sub_app_mut(RenderApp)
             .init_resource::<Sampler>()
-            .init_resource::<StorageTextureBindGroupLayout>()
             .init_resource::<TextureBindGroupLayout>()
-            .add_system(queue_texture_bind_groups.in_set(RenderSet::Queue))
-            .add_system(queue_storage_texture_bind_groups.in_set(RenderSet::Queue));
+            .init_resource::<ArrayTextureBindGroupLayout>()
+            .init_resource::<DepositBindGroupLayout>()
+            .add_system(prepare_deposit_bind_group.in_set(RenderSet::Prepare))
+            .add_system(queue_texture_bind_groups.in_set(RenderSet::Queue));
     }
 }